@@ -0,0 +1,163 @@
+use crate::error::{GarnixError, Result};
+use crate::git;
+use git2::{ObjectType, Oid};
+use std::path::{Path, PathBuf};
+
+/// On-disk cache of discovered flake attributes, one file per cache key.
+pub struct AttributeCache {
+    dir: PathBuf,
+}
+
+impl AttributeCache {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Uses `$XDG_CACHE_HOME/garnix`, falling back to `~/.cache/garnix`.
+    pub fn default_location() -> Result<Self> {
+        let base = match std::env::var_os("XDG_CACHE_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => match std::env::var_os("HOME") {
+                Some(home) => PathBuf::from(home).join(".cache"),
+                None => {
+                    return Err(GarnixError::Config(
+                        "neither XDG_CACHE_HOME nor HOME is set".to_string(),
+                    ));
+                }
+            },
+        };
+
+        Ok(Self::new(base.join("garnix")))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Derives the cache key from the flake source, its lock file and the evaluating system.
+    pub fn key_for<P: AsRef<Path>>(git_root: P, system: &str) -> Result<String> {
+        let git_root = git_root.as_ref();
+        let tree_hash = git::working_tree_hash(git_root)?;
+
+        let lock_path = git_root.join("flake.lock");
+        let lock_contents = if lock_path.exists() {
+            std::fs::read(lock_path)?
+        } else {
+            Vec::new()
+        };
+        let lock_hash = Oid::hash_object(ObjectType::Blob, &lock_contents)?;
+
+        let key = format!("{}\n{}\n{}", tree_hash, lock_hash, system);
+        Ok(Oid::hash_object(ObjectType::Blob, key.as_bytes())?.to_string())
+    }
+
+    pub fn load(&self, key: &str) -> Option<Vec<String>> {
+        let contents = std::fs::read_to_string(self.entry_path(key)).ok()?;
+        serde_json::from_str(&contents).ok()
+    }
+
+    pub fn store(&self, key: &str, attributes: &[String]) -> Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let contents = serde_json::to_string(attributes)?;
+        std::fs::write(self.entry_path(key), contents)?;
+        Ok(())
+    }
+
+    /// Removes all cached entries, returning how many were deleted.
+    pub fn clear(&self) -> Result<usize> {
+        if !self.dir.exists() {
+            return Ok(0);
+        }
+
+        let mut removed = 0;
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if is_cache_entry(&path) {
+                std::fs::remove_file(path)?;
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("attributes-{}.json", key))
+    }
+}
+
+fn is_cache_entry(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with("attributes-") && name.ends_with(".json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_store_load_and_clear() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = AttributeCache::new(dir.path().join("garnix"));
+        let attrs = vec![
+            "packages.x86_64-linux.hello".to_string(),
+            "checks.x86_64-linux.fmt".to_string(),
+        ];
+
+        assert!(cache.load("abc").is_none());
+
+        cache.store("abc", &attrs).unwrap();
+        assert_eq!(cache.load("abc"), Some(attrs));
+        assert!(cache.load("def").is_none());
+
+        std::fs::write(cache.dir().join("unrelated.txt"), "keep").unwrap();
+        assert_eq!(cache.clear().unwrap(), 1);
+        assert!(cache.load("abc").is_none());
+        assert!(cache.dir().join("unrelated.txt").exists());
+    }
+
+    #[test]
+    fn test_key_for_changes_with_the_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = git2::Repository::init(dir.path()).unwrap();
+        std::fs::write(dir.path().join("flake.nix"), "{ outputs = _: { }; }").unwrap();
+        std::fs::write(dir.path().join("logo.png"), [0u8, 1, 2, 3]).unwrap();
+        std::fs::write(dir.path().join("flake.lock"), "{}").unwrap();
+
+        let mut index = repo.index().unwrap();
+        for file in ["flake.nix", "logo.png", "flake.lock"] {
+            index.add_path(Path::new(file)).unwrap();
+        }
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("test", "test@example.com").unwrap();
+        repo.commit(Some("HEAD"), &signature, &signature, "init", &tree, &[])
+            .unwrap();
+
+        let key = || AttributeCache::key_for(dir.path(), "x86_64-linux").unwrap();
+        let committed = key();
+        assert_eq!(key(), committed);
+
+        std::fs::write(dir.path().join("flake.nix"), "{ outputs = _: { a = 1; }; }").unwrap();
+        let edited = key();
+        assert_ne!(edited, committed);
+
+        // Binary files have no textual diff, but their content still counts.
+        std::fs::write(dir.path().join("logo.png"), [0u8, 1, 2, 4]).unwrap();
+        let binary_edited = key();
+        assert_ne!(binary_edited, edited);
+        std::fs::write(dir.path().join("logo.png"), [0u8, 1, 2, 5]).unwrap();
+        assert_ne!(key(), binary_edited);
+
+        let before_lock = key();
+        std::fs::write(dir.path().join("flake.lock"), r#"{"version":7}"#).unwrap();
+        assert_ne!(key(), before_lock);
+
+        assert_ne!(
+            AttributeCache::key_for(dir.path(), "aarch64-darwin").unwrap(),
+            key()
+        );
+    }
+}
@@ -1,5 +1,11 @@
-use clap::{Parser, Subcommand};
+use crate::config::fmt::BuildsStyle;
+use crate::doctor::{Check, CheckStatus};
+use crate::duration;
+use crate::nix::{self, Builder, CacheStatus, DryRunPlan, EvalBackend};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use colored::*;
+use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Parser)]
 #[command(name = "garnix")]
@@ -7,6 +13,11 @@ use colored::*;
 #[command(about = "CLI tooling for garnix")]
 #[command(long_about = None)]
 pub struct Cli {
+    /// Extra config file merged on top of garnix.yaml and garnix.local.yaml
+    /// (repeatable, applied in order)
+    #[arg(long = "config", value_name = "PATH", global = true)]
+    pub config: Vec<PathBuf>,
+
     #[command(subcommand)]
     pub command: Commands,
 }
@@ -14,17 +25,202 @@ pub struct Cli {
 #[derive(Subcommand)]
 pub enum Commands {
     /// Run builds according to garnix.yaml configuration
-    Run {
+    Run(RunArgs),
+
+    /// Show how the build set changes between two revisions of garnix.yaml
+    Diff {
+        /// Revision to compare from
+        #[arg(long, value_name = "REV", default_value = "HEAD")]
+        from: String,
+
+        /// Revision to compare to (defaults to the working tree)
+        #[arg(long, value_name = "REV")]
+        to: Option<String>,
+
+        /// Always re-run `nix flake show` instead of using cached attributes
+        #[arg(long, action)]
+        no_cache: bool,
+    },
+
+    /// List discovered flake attributes and whether they match garnix.yaml
+    List {
         /// Override the current git branch for configuration filtering
         #[arg(long, value_name = "BRANCH")]
         as_branch: Option<String>,
 
-        /// Don't actually run builds, just output the list of builds that *would* have run
+        /// List every discovered attribute (the default)
+        #[arg(long, action, conflicts_with_all = ["matched", "unmatched"])]
+        all: bool,
+
+        /// Only list attributes that would be built
+        #[arg(long, action, conflicts_with = "unmatched")]
+        matched: bool,
+
+        /// Only list attributes that would not be built
+        #[arg(long, action)]
+        unmatched: bool,
+
+        /// Only list attributes in this output category (e.g. packages, checks)
+        #[arg(long, value_name = "CATEGORY")]
+        category: Option<String>,
+
+        /// Output format
+        #[arg(long, value_enum, default_value_t = OutputFormat::Plain)]
+        format: OutputFormat,
+
+        /// Always re-run `nix flake show` instead of using cached attributes
+        #[arg(long, action)]
+        no_cache: bool,
+    },
+
+    /// Scaffold a garnix.yaml that builds the flake's current outputs
+    Init {
+        /// Overwrite an existing garnix.yaml
+        #[arg(long, action)]
+        force: bool,
+
+        /// Only preview the generated file, don't write it
         #[arg(long, action)]
         dry_run: bool,
+
+        /// Always re-run `nix flake show` instead of using cached attributes
+        #[arg(long, action)]
+        no_cache: bool,
+    },
+
+    /// Check that nix, flakes and the repository are set up for garnix
+    Doctor,
+
+    /// Manage the discovered attribute cache
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommands,
+    },
+
+    /// Inspect and maintain garnix.yaml
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
     },
 }
 
+#[derive(Args)]
+pub struct RunArgs {
+    /// Override the current git branch for configuration filtering
+    #[arg(long, value_name = "BRANCH")]
+    pub as_branch: Option<String>,
+
+    /// Don't build anything, report what nix would build and fetch instead
+    #[arg(long, action)]
+    pub dry_run: bool,
+
+    /// Only build matched attributes that also match this pattern (repeatable)
+    #[arg(long, value_name = "PATTERN")]
+    pub only: Vec<String>,
+
+    /// Don't build matched attributes that match this pattern (repeatable)
+    #[arg(long, value_name = "PATTERN")]
+    pub skip: Vec<String>,
+
+    /// Always re-run `nix flake show` instead of using cached attributes
+    #[arg(long, action)]
+    pub no_cache: bool,
+
+    /// Don't build attributes whose outputs are already in the local store
+    /// (attributes a substituter has are still fetched)
+    #[arg(long, action)]
+    pub skip_cached: bool,
+
+    /// Program used to run builds (the nix binary can be overridden with GARNIX_NIX_BIN)
+    #[arg(long, value_enum, default_value_t = Builder::Nix)]
+    pub builder: Builder,
+
+    /// How matched attributes are evaluated before building
+    #[arg(long, value_enum, default_value_t = EvalBackend::Auto)]
+    pub eval_backend: EvalBackend,
+
+    /// Number of attributes built at once (defaults to the number of CPUs, nom builds one at a time)
+    #[arg(short, long, value_name = "N")]
+    pub jobs: Option<usize>,
+
+    /// Number of nix-eval-jobs workers (defaults to the number of CPUs)
+    #[arg(long, value_name = "N")]
+    pub eval_workers: Option<usize>,
+
+    /// Kill and report attributes whose build takes longer than this (e.g. 30m, 1h).
+    /// Overrides `timeout` on garnix.yaml build entries
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse_duration)]
+    pub timeout: Option<Duration>,
+
+    /// Number of log lines to show for each failed attribute
+    #[arg(long, value_name = "LINES", default_value_t = 20)]
+    pub log_tail: usize,
+
+    /// Don't list attributes that are built only since excludes became entry-scoped
+    #[arg(long, action)]
+    pub no_exclude_warning: bool,
+
+    /// Fail instead of warning when garnix.yaml doesn't match the flake's outputs
+    #[arg(long, action)]
+    pub strict: bool,
+}
+
+#[derive(Subcommand)]
+pub enum CacheCommands {
+    /// Remove all cached `nix flake show` results
+    Clear,
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommands {
+    /// Print the JSON Schema for garnix.yaml (for yaml-language-server and friends)
+    Schema,
+
+    /// Print the config files garnix reads, or the resolved config with --effective
+    Show {
+        /// Print the fully resolved config, annotating where each value comes from
+        #[arg(long, action)]
+        effective: bool,
+
+        /// Resolve for this branch instead of the current git branch
+        #[arg(long, value_name = "BRANCH", requires = "effective")]
+        branch: Option<String>,
+    },
+
+    /// Check garnix.yaml for shadowed and contradictory build entries
+    Lint {
+        /// Don't report this rule, by ID (GX001) or name (covered-include)
+        #[arg(long, value_name = "RULE")]
+        allow: Vec<String>,
+
+        /// File to lint (defaults to the repository's config file)
+        #[arg(value_name = "PATH")]
+        path: Option<PathBuf>,
+    },
+
+    /// Rewrite garnix.yaml in canonical form, keeping comments
+    Fmt {
+        /// Don't write anything, exit non-zero if the file isn't canonical
+        #[arg(long, action)]
+        check: bool,
+
+        /// Shape to write `builds` in (several entries are always a list)
+        #[arg(long, value_enum, default_value_t = BuildsStyle::List)]
+        builds: BuildsStyle,
+
+        /// File to format (defaults to the repository's config file)
+        #[arg(value_name = "PATH")]
+        path: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Plain,
+    Tree,
+    Json,
+}
+
 pub fn print_success(message: &str) {
     println!("{}", message.green());
 }
@@ -44,3 +240,97 @@ pub fn print_info(message: &str) {
 pub fn print_build_target(target: &str) {
     println!("    {}", target.cyan());
 }
+
+pub fn print_added(target: &str) {
+    println!("    {} {}", "+".green(), target.green());
+}
+
+pub fn print_removed(target: &str) {
+    println!("    {} {}", "-".red(), target.red());
+}
+
+/// Prints a unified diff of `old` -> `new`, labelled with `file`.
+pub fn print_file_diff(file: &str, old: &str, new: &str) {
+    let diff = similar::TextDiff::from_lines(old, new);
+    let (old_label, new_label) = if file.starts_with('/') {
+        (file.to_string(), file.to_string())
+    } else {
+        (format!("a/{}", file), format!("b/{}", file))
+    };
+    let unified = diff
+        .unified_diff()
+        .header(&old_label, &new_label)
+        .to_string();
+
+    for line in unified.lines() {
+        if line.starts_with("+++") || line.starts_with("---") {
+            println!("{}", line.bold());
+        } else if line.starts_with('+') {
+            println!("{}", line.green());
+        } else if line.starts_with('-') {
+            println!("{}", line.red());
+        } else if line.starts_with("@@") {
+            println!("{}", line.cyan());
+        } else {
+            println!("{}", line);
+        }
+    }
+}
+
+pub fn print_attribute_status(target: &str, status: CacheStatus) {
+    let label = format!("[{}]", status);
+    let label = match status {
+        CacheStatus::Cached => label.green(),
+        CacheStatus::WillSubstitute => label.blue(),
+        CacheStatus::WillBuild => label.yellow(),
+    };
+    println!("    {} {}", target.cyan(), label);
+}
+
+pub fn print_dry_run_plan(plan: &DryRunPlan) {
+    if plan.is_empty() {
+        print_success("dry-run: everything is already in the local store");
+        return;
+    }
+
+    if !plan.will_build.is_empty() {
+        print_info(&format!(
+            "dry-run: {} derivations would be built:",
+            plan.will_build.len()
+        ));
+        for drv in &plan.will_build {
+            println!("    {}", drv);
+        }
+    }
+
+    if !plan.will_fetch.is_empty() {
+        print_info(&format!(
+            "dry-run: {} paths would be fetched ({}{} download, {} unpacked):",
+            plan.will_fetch.len(),
+            if plan.shared_fetches { "at most " } else { "" },
+            nix::format_size(plan.download_bytes),
+            nix::format_size(plan.unpacked_bytes)
+        ));
+        for path in &plan.will_fetch {
+            println!("    {}", path);
+        }
+    }
+}
+
+pub fn print_check(check: &Check) {
+    let (marker, name) = match check.status {
+        CheckStatus::Ok => ("ok".green(), check.name.green()),
+        CheckStatus::Warning => ("warn".yellow(), check.name.yellow()),
+        CheckStatus::Failed => ("fail".red(), check.name.red()),
+    };
+    let mut lines = check.detail.lines();
+    println!(
+        "[{}] {}: {}",
+        marker,
+        name,
+        lines.next().unwrap_or_default()
+    );
+    for line in lines {
+        println!("    {}", line);
+    }
+}
@@ -0,0 +1,471 @@
+use crate::config::interpolate::UnsetVariables;
+use crate::config::locate::LineMap;
+use crate::config::merge::{ConfigOverride, MergeMode};
+use crate::config::parser::{ConfigLayer, LayerKind};
+use crate::config::types::{
+    BuildEntry, BuildsConfig, DeploymentConfig, GarnixConfig, IncrementalizeBuilds, ServerConfig,
+};
+use crate::duration::format_duration;
+use crate::error::Result;
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+
+/// Where an effective config value came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// A `#[serde(default)]` value, nothing set it.
+    Default,
+    File {
+        kind: LayerKind,
+        file: String,
+        line: Option<usize>,
+    },
+    CurrentBranch,
+    Flag(&'static str),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File { kind, file, line } => {
+                match kind {
+                    LayerKind::Config => {}
+                    LayerKind::LocalOverride => write!(f, "local override ")?,
+                    LayerKind::Flag => write!(f, "--config ")?,
+                }
+                write!(f, "{}", file)?;
+                match line {
+                    Some(line) => write!(f, ":{}", line),
+                    None => Ok(()),
+                }
+            }
+            Source::CurrentBranch => write!(f, "current git branch"),
+            Source::Flag(flag) => write!(f, "{}", flag),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sourced<T> {
+    pub value: T,
+    pub source: Source,
+}
+
+impl<T> Sourced<T> {
+    pub fn new(value: T, source: Source) -> Self {
+        Self { value, source }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EffectiveEntry {
+    /// Where the entry itself was written.
+    pub origin: Source,
+    pub include: Sourced<Vec<String>>,
+    pub exclude: Sourced<Vec<String>>,
+    pub branch: Sourced<Option<String>>,
+    pub timeout: Sourced<Option<Duration>>,
+    /// Whether the entry applies to the branch the config was resolved for.
+    pub applies: bool,
+}
+
+impl EffectiveEntry {
+    pub fn to_build_entry(&self) -> BuildEntry {
+        BuildEntry {
+            include: self.include.value.clone(),
+            exclude: self.exclude.value.clone(),
+            branch: self.branch.value.clone(),
+            timeout: self.timeout.value,
+        }
+    }
+}
+
+/// The config after defaults, override layers and branch filtering, with
+/// the source of every value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EffectiveConfig {
+    pub branch: Sourced<String>,
+    pub builds: Vec<EffectiveEntry>,
+    pub incrementalize_builds: Sourced<IncrementalizeBuilds>,
+    pub servers: Sourced<Vec<ServerConfig>>,
+}
+
+/// Applies `layers` (see `read_layers`) in order the same way
+/// `load_layered_config` does, keeping track of where each value came from.
+/// File names are shown relative to `git_root`. Unset variables are shown as
+/// written rather than failing.
+pub fn resolve(
+    layers: &[ConfigLayer],
+    git_root: &Path,
+    branch: Sourced<String>,
+) -> Result<EffectiveConfig> {
+    let mut builds = entries_from(&BuildsConfig::default(), &LineMap::default(), |_| {
+        Source::Default
+    });
+    let mut incrementalize_builds = Sourced::new(IncrementalizeBuilds::default(), Source::Default);
+    let mut servers = Sourced::new(Vec::new(), Source::Default);
+
+    for layer in layers {
+        let lines = LineMap::parse(&layer.contents);
+        let file = layer
+            .path
+            .strip_prefix(git_root)
+            .unwrap_or(&layer.path)
+            .display()
+            .to_string();
+        let source_at = |line: Option<usize>| Source::File {
+            kind: layer.kind,
+            file: file.clone(),
+            line,
+        };
+
+        // A full config only sets what it mentions, everything else keeps
+        // its default, so it behaves like an override that replaces builds.
+        let overlay = match layer.kind {
+            LayerKind::Config => {
+                let config: GarnixConfig = layer.parse(UnsetVariables::Keep)?;
+                ConfigOverride {
+                    builds: lines.line("builds").map(|_| config.builds),
+                    builds_merge: MergeMode::Replace,
+                    incrementalize_builds: lines
+                        .line("incrementalizeBuilds")
+                        .map(|_| config.incrementalize_builds),
+                    servers: lines.line("servers").map(|_| config.servers),
+                }
+            }
+            LayerKind::LocalOverride | LayerKind::Flag => layer.parse(UnsetVariables::Keep)?,
+        };
+
+        if let Some(layer_builds) = &overlay.builds {
+            let entries = entries_from(layer_builds, &lines, source_at);
+            match overlay.builds_merge {
+                MergeMode::Append => builds.extend(entries),
+                MergeMode::Replace => builds = entries,
+            }
+        }
+        if let Some(value) = overlay.incrementalize_builds {
+            incrementalize_builds =
+                Sourced::new(value, source_at(lines.line("incrementalizeBuilds")));
+        }
+        if let Some(value) = overlay.servers {
+            servers = Sourced::new(value, source_at(lines.line("servers")));
+        }
+    }
+
+    for entry in &mut builds {
+        entry.applies = entry
+            .branch
+            .value
+            .as_ref()
+            .is_none_or(|entry_branch| *entry_branch == branch.value);
+    }
+
+    Ok(EffectiveConfig {
+        branch,
+        builds,
+        incrementalize_builds,
+        servers,
+    })
+}
+
+fn entries_from(
+    builds: &BuildsConfig,
+    lines: &LineMap,
+    source_at: impl Fn(Option<usize>) -> Source,
+) -> Vec<EffectiveEntry> {
+    builds
+        .entries()
+        .into_iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            let key_source = |key: &str| match lines.entry_key_line(index, key) {
+                Some(line) => source_at(Some(line)),
+                None => Source::Default,
+            };
+            EffectiveEntry {
+                origin: match lines.entry_line(index) {
+                    Some(line) => source_at(Some(line)),
+                    None => Source::Default,
+                },
+                include: Sourced::new(entry.include.clone(), key_source("include")),
+                exclude: Sourced::new(entry.exclude.clone(), key_source("exclude")),
+                branch: Sourced::new(entry.branch.clone(), key_source("branch")),
+                timeout: Sourced::new(entry.timeout, key_source("timeout")),
+                applies: true,
+            }
+        })
+        .collect()
+}
+
+/// Renders the effective config as YAML, with each value's source as a
+/// trailing comment.
+pub fn render(effective: &EffectiveConfig) -> String {
+    let mut lines: Vec<(String, Option<String>)> = vec![(
+        format!("# branch: {}", effective.branch.value),
+        Some(effective.branch.source.to_string()),
+    )];
+
+    let applied: Vec<&EffectiveEntry> = effective.builds.iter().filter(|e| e.applies).collect();
+    let skipped: Vec<&EffectiveEntry> = effective.builds.iter().filter(|e| !e.applies).collect();
+    lines.push((
+        if applied.is_empty() {
+            "builds: []"
+        } else {
+            "builds:"
+        }
+        .to_string(),
+        None,
+    ));
+
+    for entry in &applied {
+        lines.push((format!("  # entry from {}", entry.origin), None));
+        push_patterns(&mut lines, "  - include", &entry.include);
+        push_patterns(&mut lines, "    exclude", &entry.exclude);
+        lines.push((
+            format!(
+                "    branch: {}",
+                entry
+                    .branch
+                    .value
+                    .as_deref()
+                    .map_or("null".to_string(), yaml_string)
+            ),
+            Some(entry.branch.source.to_string()),
+        ));
+        lines.push((
+            format!(
+                "    timeout: {}",
+                entry
+                    .timeout
+                    .value
+                    .map_or("null".to_string(), format_duration)
+            ),
+            Some(entry.timeout.source.to_string()),
+        ));
+    }
+    for entry in &skipped {
+        lines.push((
+            format!(
+                "  # skipped: entry from {} only applies to branch {}",
+                entry.origin,
+                entry.branch.value.as_deref().unwrap_or_default()
+            ),
+            None,
+        ));
+    }
+
+    let incrementalize = match &effective.incrementalize_builds.value {
+        IncrementalizeBuilds::Boolean(value) => format!("incrementalizeBuilds: {}", value),
+        IncrementalizeBuilds::ExcludesBranches { exclude_branches } => format!(
+            "incrementalizeBuilds: {{exclude_branches: {}}}",
+            serde_json::to_string(exclude_branches).unwrap_or_default()
+        ),
+    };
+    lines.push((
+        incrementalize,
+        Some(effective.incrementalize_builds.source.to_string()),
+    ));
+
+    let servers = &effective.servers;
+    lines.push((
+        if servers.value.is_empty() {
+            "servers: []"
+        } else {
+            "servers:"
+        }
+        .to_string(),
+        Some(servers.source.to_string()),
+    ));
+    for server in &servers.value {
+        lines.push((
+            format!("  - configuration: {}", yaml_string(&server.configuration)),
+            None,
+        ));
+        lines.push(("    deployment:".to_string(), None));
+        match &server.deployment {
+            DeploymentConfig::OnPullRequest => {
+                lines.push(("      type: on-pull-request".to_string(), None));
+            }
+            DeploymentConfig::OnBranch { branch } => {
+                lines.push(("      type: on-branch".to_string(), None));
+                lines.push((format!("      branch: {}", yaml_string(branch)), None));
+            }
+        }
+    }
+
+    let width = lines
+        .iter()
+        .filter(|(_, annotation)| annotation.is_some())
+        .map(|(text, _)| text.len())
+        .max()
+        .unwrap_or(0);
+    let mut output = String::new();
+    for (text, annotation) in lines {
+        match annotation {
+            Some(annotation) => output.push_str(&format!(
+                "{:width$}  # {}\n",
+                text,
+                annotation,
+                width = width
+            )),
+            None => output.push_str(&format!("{}\n", text)),
+        }
+    }
+    output
+}
+
+fn push_patterns(
+    lines: &mut Vec<(String, Option<String>)>,
+    key: &str,
+    patterns: &Sourced<Vec<String>>,
+) {
+    let source = Some(patterns.source.to_string());
+    if patterns.value.is_empty() {
+        lines.push((format!("{}: []", key), source));
+        return;
+    }
+    lines.push((format!("{}:", key), source));
+    for pattern in &patterns.value {
+        lines.push((
+            format!(
+                "      - {}",
+                serde_json::to_string(pattern).unwrap_or_default()
+            ),
+            None,
+        ));
+    }
+}
+
+fn yaml_string(value: &str) -> String {
+    serde_yaml::to_string(value)
+        .map(|yaml| yaml.trim().to_string())
+        .unwrap_or_else(|_| value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::parser::{load_layered_config, read_layers};
+    use std::path::PathBuf;
+
+    fn write(dir: &Path, name: &str, contents: &str) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn branch(name: &str) -> Sourced<String> {
+        Sourced::new(name.to_string(), Source::Flag("--branch"))
+    }
+
+    fn file(kind: LayerKind, file: &str, line: usize) -> Source {
+        Source::File {
+            kind,
+            file: file.to_string(),
+            line: Some(line),
+        }
+    }
+
+    #[test]
+    fn test_defaults_without_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let effective = resolve(&[], dir.path(), branch("main")).unwrap();
+
+        assert_eq!(effective.builds.len(), 1);
+        let entry = &effective.builds[0];
+        assert_eq!(entry.include.source, Source::Default);
+        assert!(
+            entry
+                .include
+                .value
+                .contains(&"*.x86_64-linux.*".to_string())
+        );
+        assert!(entry.applies);
+        assert_eq!(effective.servers.source, Source::Default);
+    }
+
+    #[test]
+    fn test_sources_across_layers() {
+        let dir = tempfile::tempdir().unwrap();
+        write(
+            dir.path(),
+            "garnix.yaml",
+            "builds:\n  - include: [\"packages.*.*\"]\n    branch: main\n  - exclude: [\"checks.*.*\"]\n    branch: dev\nincrementalizeBuilds: true\n",
+        );
+        write(
+            dir.path(),
+            "garnix.local.yaml",
+            "builds:\n  - include: [\"checks.*.slow\"]\n    timeout: 2h\n",
+        );
+        let ci = write(dir.path(), "ci.yaml", "servers: []\n");
+
+        let layers = read_layers(dir.path(), std::slice::from_ref(&ci)).unwrap();
+        let effective = resolve(&layers, dir.path(), branch("main")).unwrap();
+
+        let base = &effective.builds[0];
+        assert_eq!(base.origin, file(LayerKind::Config, "garnix.yaml", 2));
+        assert_eq!(
+            base.include.source,
+            file(LayerKind::Config, "garnix.yaml", 2)
+        );
+        assert_eq!(base.exclude.source, Source::Default);
+        assert_eq!(
+            base.branch.source,
+            file(LayerKind::Config, "garnix.yaml", 3)
+        );
+        assert!(base.applies);
+
+        let dev_only = &effective.builds[1];
+        assert_eq!(dev_only.include.source, Source::Default);
+        assert!(!dev_only.applies);
+
+        let local = &effective.builds[2];
+        assert_eq!(
+            local.timeout.source,
+            file(LayerKind::LocalOverride, "garnix.local.yaml", 3)
+        );
+        assert_eq!(
+            effective.incrementalize_builds.source,
+            file(LayerKind::Config, "garnix.yaml", 6)
+        );
+        // Files inside the repository are shown relative to it.
+        assert_eq!(effective.servers.source.to_string(), "--config ci.yaml:1");
+
+        // Same entries as the plain layered loader.
+        let loaded = load_layered_config(dir.path(), &[ci])
+            .unwrap()
+            .config
+            .unwrap();
+        let expected: Vec<BuildEntry> = loaded.builds.entries().into_iter().cloned().collect();
+        let resolved: Vec<BuildEntry> = effective
+            .builds
+            .iter()
+            .map(EffectiveEntry::to_build_entry)
+            .collect();
+        assert_eq!(resolved, expected);
+    }
+
+    #[test]
+    fn test_render() {
+        let dir = tempfile::tempdir().unwrap();
+        write(
+            dir.path(),
+            "garnix.yaml",
+            "builds:\n  include: [\"packages.*.*\"]\n  branch: dev\n",
+        );
+        let layers = read_layers(dir.path(), &[]).unwrap();
+        let effective = resolve(&layers, dir.path(), branch("main")).unwrap();
+
+        assert_eq!(
+            render(&effective),
+            r#"# branch: main               # --branch
+builds: []
+  # skipped: entry from garnix.yaml:1 only applies to branch dev
+incrementalizeBuilds: false  # default
+servers: []                  # default
+"#
+        );
+    }
+}
@@ -0,0 +1,688 @@
+use crate::config::types::{BuildEntry, BuildsConfig, GarnixConfig};
+use crate::error::{GarnixError, Result};
+use crate::matcher::pattern_covers;
+use clap::ValueEnum;
+use serde_yaml::Value;
+use std::collections::HashMap;
+
+/// Which shape `builds` is written in. A config with more than one entry is
+/// always written as a list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum BuildsStyle {
+    /// `builds: [- include: ...]`
+    #[default]
+    List,
+    /// `builds: {include: ...}`
+    Single,
+}
+
+const PATTERN_KEYS: [&str; 2] = ["include", "exclude"];
+const SCALAR_KEYS: [&str; 2] = ["branch", "timeout"];
+
+/// Sorts and deduplicates patterns and drops any pattern covered by a broader
+/// one in the same list. Returns the kept patterns and, for each dropped
+/// pattern, the pattern that replaced it.
+pub fn canonical_patterns(patterns: &[String]) -> (Vec<String>, Vec<(String, String)>) {
+    let mut sorted = patterns.to_vec();
+    sorted.sort();
+    sorted.dedup();
+
+    let mut kept = Vec::new();
+    let mut shadowed = Vec::new();
+    for pattern in &sorted {
+        match sorted
+            .iter()
+            .find(|other| *other != pattern && pattern_covers(other, pattern))
+        {
+            Some(broader) => shadowed.push((pattern.clone(), broader.clone())),
+            None => kept.push(pattern.clone()),
+        }
+    }
+
+    (kept, shadowed)
+}
+
+/// Formats garnix.yaml: `builds` is rewritten in `style` with canonical
+/// pattern lists and keys in a fixed order, comments are carried over, and
+/// everything outside `builds` is left untouched.
+pub fn format_config(contents: &str, file: &str, style: BuildsStyle) -> Result<String> {
+    let config: GarnixConfig =
+        serde_yaml::from_str(contents).map_err(|e| GarnixError::invalid_config(file, e))?;
+    let document: Value =
+        serde_yaml::from_str(contents).map_err(|e| GarnixError::invalid_config(file, e))?;
+
+    let Some(builds) = document.get("builds") else {
+        return Ok(contents.to_string());
+    };
+
+    let lines: Vec<&str> = contents.lines().collect();
+    let (start, end) = builds_block(&lines).ok_or_else(|| {
+        GarnixError::Config(format!(
+            "{}: `builds` must be a top-level `builds:` key to be formatted",
+            file
+        ))
+    })?;
+
+    let entries = match builds {
+        Value::Sequence(entries) => entries.iter().map(FmtEntry::from_value).collect(),
+        Value::Mapping(_) => vec![FmtEntry::from_value(builds)],
+        _ => Vec::new(),
+    };
+    let comments = CommentMap::harvest(&lines[start..end], matches!(builds, Value::Sequence(_)));
+
+    let mut output: Vec<String> = lines[..start].iter().map(|line| line.to_string()).collect();
+    output.extend(render_builds(entries, comments, style));
+    output.extend(lines[end..].iter().map(|line| line.to_string()));
+    let mut formatted = output.join("\n");
+    formatted.push('\n');
+
+    // The rewrite is line-based, so double check it didn't change the config
+    // beyond what formatting is meant to change.
+    let reparsed: GarnixConfig = serde_yaml::from_str(&formatted).map_err(|e| {
+        GarnixError::Config(format!("formatting {} produced invalid YAML: {}", file, e))
+    })?;
+    if canonical_entries(&config.builds) != canonical_entries(&reparsed.builds)
+        || config.incrementalize_builds != reparsed.incrementalize_builds
+        || config.servers != reparsed.servers
+    {
+        return Err(GarnixError::Config(format!(
+            "formatting {} would change its meaning, leaving it as is",
+            file
+        )));
+    }
+
+    Ok(formatted)
+}
+
+fn canonical_entries(builds: &BuildsConfig) -> Vec<BuildEntry> {
+    builds
+        .entries()
+        .into_iter()
+        .map(|entry| BuildEntry {
+            include: canonical_patterns(&entry.include).0,
+            exclude: canonical_patterns(&entry.exclude).0,
+            ..entry.clone()
+        })
+        .collect()
+}
+
+/// The lines from `builds:` up to the next top-level key. Unindented
+/// comments right before that key stay with it.
+fn builds_block(lines: &[&str]) -> Option<(usize, usize)> {
+    let start = lines.iter().position(|line| {
+        line.strip_prefix("builds")
+            .is_some_and(|rest| rest.trim_start().starts_with(':'))
+    })?;
+
+    let mut end = lines[start + 1..]
+        .iter()
+        .position(|line| !line.trim().is_empty() && !line.starts_with([' ', '\t', '#']))
+        .map_or(lines.len(), |offset| start + 1 + offset);
+    while end > start + 1 && (lines[end - 1].trim().is_empty() || lines[end - 1].starts_with('#')) {
+        end -= 1;
+    }
+
+    Some((start, end))
+}
+
+/// One build entry as written, keeping which keys were present.
+#[derive(Debug, Default)]
+struct FmtEntry {
+    patterns: Vec<(String, Vec<String>)>,
+    scalars: Vec<(String, Value)>,
+    other: Vec<(String, Value)>,
+}
+
+impl FmtEntry {
+    fn from_value(value: &Value) -> Self {
+        let mut entry = FmtEntry::default();
+        let Value::Mapping(mapping) = value else {
+            return entry;
+        };
+
+        for (key, value) in mapping {
+            let key = match key {
+                Value::String(key) => key.clone(),
+                other => serde_yaml::to_string(other)
+                    .unwrap_or_default()
+                    .trim()
+                    .to_string(),
+            };
+            if PATTERN_KEYS.contains(&key.as_str()) {
+                let patterns = value
+                    .as_sequence()
+                    .map(|items| {
+                        items
+                            .iter()
+                            .filter_map(|item| item.as_str().map(str::to_string))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                entry.patterns.push((key, patterns));
+            } else if SCALAR_KEYS.contains(&key.as_str()) {
+                entry.scalars.push((key, value.clone()));
+            } else {
+                entry.other.push((key, value.clone()));
+            }
+        }
+
+        entry
+            .patterns
+            .sort_by_key(|(key, _)| PATTERN_KEYS.iter().position(|k| k == key));
+        entry
+            .scalars
+            .sort_by_key(|(key, _)| SCALAR_KEYS.iter().position(|k| k == key));
+        entry
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Anchor {
+    Header,
+    Entry(usize),
+    /// Comments after an entry's last key, indented as part of the entry.
+    EntryEnd(usize),
+    Key(usize, String),
+    Pattern(usize, String, String),
+}
+
+#[derive(Debug, Default)]
+struct Comments {
+    leading: Vec<String>,
+    trailing: Option<String>,
+}
+
+/// Comments in the `builds` block, keyed by what they were written next to.
+#[derive(Debug, Default)]
+struct CommentMap {
+    comments: HashMap<Anchor, Comments>,
+    /// Comments that couldn't be tied to anything, kept at the end of the block.
+    dangling: Vec<String>,
+}
+
+impl CommentMap {
+    fn harvest(block: &[&str], list_form: bool) -> Self {
+        let mut map = CommentMap::default();
+        // Comments waiting for the next anchor, with the column they start at.
+        let mut pending: Vec<(usize, String)> = Vec::new();
+        let mut entry: Option<usize> = if list_form { None } else { Some(0) };
+        let mut entry_indent: Option<usize> = None;
+        let mut key: Option<String> = None;
+        let mut in_flow = false;
+
+        for (index, line) in block.iter().enumerate() {
+            let (content, comment) = split_comment(line);
+            let mut anchors = Vec::new();
+
+            if index == 0 {
+                anchors.push(Anchor::Header);
+            } else if content.trim().is_empty() {
+                let column = line.len() - line.trim_start().len();
+                pending.extend(comment.map(|comment| (column, comment)));
+                continue;
+            } else if in_flow {
+                if let (Some(entry), Some(key)) = (entry, &key) {
+                    for item in flow_items(content) {
+                        anchors.push(Anchor::Pattern(entry, key.clone(), item));
+                    }
+                }
+                in_flow = !content.contains(']');
+            } else {
+                let indent = content.len() - content.trim_start().len();
+                let mut rest = content.trim_start();
+
+                let item = rest
+                    .strip_prefix('-')
+                    .filter(|after| after.is_empty() || after.starts_with(' '));
+                if let Some(after) = item {
+                    let after = after.trim_start();
+                    if list_form && entry_indent.is_none_or(|entry_indent| indent <= entry_indent) {
+                        if let Some(previous) = entry {
+                            map.close_entry(previous, indent, &mut pending);
+                        }
+                        let next = entry.map_or(0, |entry| entry + 1);
+                        entry = Some(next);
+                        entry_indent = Some(indent);
+                        key = None;
+                        anchors.push(Anchor::Entry(next));
+                        rest = after;
+                    } else {
+                        if let (Some(entry), Some(key)) = (entry, &key) {
+                            anchors.push(Anchor::Pattern(entry, key.clone(), scalar(after)));
+                        }
+                        rest = "";
+                    }
+                }
+
+                if let (Some(entry), Some((name, value))) = (entry, rest.split_once(':')) {
+                    let name = name.trim().to_string();
+                    let value = value.trim();
+                    anchors.push(Anchor::Key(entry, name.clone()));
+                    if value.starts_with('[') {
+                        for item in flow_items(value) {
+                            anchors.push(Anchor::Pattern(entry, name.clone(), item));
+                        }
+                        in_flow = !value.contains(']');
+                    }
+                    key = Some(name);
+                }
+            }
+
+            // An inline comment belongs to the key on its line, or to the
+            // pattern when the line is just a list item.
+            let inline_anchor = anchors
+                .iter()
+                .rev()
+                .find(|anchor| !matches!(anchor, Anchor::Pattern(..)))
+                .or(anchors.last());
+            match (anchors.first(), inline_anchor) {
+                (Some(first), Some(last)) => {
+                    let first = first.clone();
+                    let last = last.clone();
+                    map.comments
+                        .entry(first)
+                        .or_default()
+                        .leading
+                        .extend(pending.drain(..).map(|(_, comment)| comment));
+                    if let Some(comment) = comment {
+                        let comments = map.comments.entry(last).or_default();
+                        match &comments.trailing {
+                            Some(_) => comments.leading.push(comment),
+                            None => comments.trailing = Some(comment),
+                        }
+                    }
+                }
+                _ => {
+                    let column = content.len() - content.trim_start().len();
+                    pending.extend(comment.map(|comment| (column, comment)));
+                }
+            }
+        }
+
+        if let Some(last) = entry {
+            map.close_entry(last, entry_indent.unwrap_or(0), &mut pending);
+        }
+        map.dangling = pending.into_iter().map(|(_, comment)| comment).collect();
+        map
+    }
+
+    /// Hands pending comments indented deeper than `indent` (the next
+    /// entry's `- `, or the block's end) to the end of `entry`.
+    fn close_entry(&mut self, entry: usize, indent: usize, pending: &mut Vec<(usize, String)>) {
+        let split = pending
+            .iter()
+            .position(|(column, _)| *column <= indent)
+            .unwrap_or(pending.len());
+        let comments: Vec<String> = pending.drain(..split).map(|(_, comment)| comment).collect();
+        if !comments.is_empty() {
+            self.comments
+                .entry(Anchor::EntryEnd(entry))
+                .or_default()
+                .leading
+                .extend(comments);
+        }
+    }
+
+    fn take(&mut self, anchor: &Anchor) -> Comments {
+        self.comments.remove(anchor).unwrap_or_default()
+    }
+
+    /// Moves the comments of a pattern dropped by `canonical_patterns` onto
+    /// the pattern that replaced it.
+    fn move_pattern(&mut self, entry: usize, key: &str, from: &str, to: &str) {
+        let from = self.take(&Anchor::Pattern(entry, key.to_string(), from.to_string()));
+        let to = self
+            .comments
+            .entry(Anchor::Pattern(entry, key.to_string(), to.to_string()))
+            .or_default();
+        to.leading.extend(from.leading);
+        to.leading.extend(from.trailing);
+    }
+}
+
+/// Splits a line into its content and a trailing `# comment`, ignoring `#`
+/// inside quotes or glued to a word.
+fn split_comment(line: &str) -> (&str, Option<String>) {
+    let mut in_single = false;
+    let mut in_double = false;
+    let mut escaped = false;
+    let mut previous = ' ';
+
+    for (index, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_double => escaped = true,
+            '"' if !in_single => in_double = !in_double,
+            '\'' if !in_double => in_single = !in_single,
+            '#' if !in_single && !in_double && previous.is_whitespace() => {
+                return (
+                    line[..index].trim_end(),
+                    Some(line[index..].trim().to_string()),
+                );
+            }
+            _ => {}
+        }
+        previous = c;
+    }
+
+    (line.trim_end(), None)
+}
+
+/// The items of a (possibly partial) flow sequence like `["a", "b",`.
+fn flow_items(text: &str) -> Vec<String> {
+    let text = text.trim().trim_start_matches('[');
+    let text = text.split(']').next().unwrap_or_default();
+
+    let mut items = Vec::new();
+    let mut current = String::new();
+    let mut quote: Option<char> = None;
+    for c in text.chars() {
+        match (c, quote) {
+            ('"' | '\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            (',', None) => {
+                items.push(std::mem::take(&mut current));
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    items.push(current);
+
+    items
+        .iter()
+        .map(|item| item.trim())
+        .filter(|item| !item.is_empty())
+        .map(scalar)
+        .collect()
+}
+
+fn scalar(text: &str) -> String {
+    serde_yaml::from_str::<String>(text).unwrap_or_else(|_| text.trim().to_string())
+}
+
+fn quote_pattern(pattern: &str) -> String {
+    serde_json::to_string(pattern).unwrap_or_else(|_| format!("\"{}\"", pattern))
+}
+
+fn render_value(value: &Value) -> String {
+    match value {
+        Value::String(_) | Value::Number(_) | Value::Bool(_) | Value::Null => {
+            serde_yaml::to_string(value)
+                .unwrap_or_default()
+                .trim()
+                .to_string()
+        }
+        _ => serde_json::to_string(value).unwrap_or_default(),
+    }
+}
+
+fn with_trailing(line: String, trailing: Option<String>) -> String {
+    match trailing {
+        Some(comment) => format!("{} {}", line, comment),
+        None => line,
+    }
+}
+
+fn push_comments(output: &mut Vec<String>, indent: usize, comments: Vec<String>) {
+    for comment in comments {
+        output.push(format!("{}{}", " ".repeat(indent), comment));
+    }
+}
+
+fn render_builds(
+    entries: Vec<FmtEntry>,
+    mut comments: CommentMap,
+    style: BuildsStyle,
+) -> Vec<String> {
+    let single = style == BuildsStyle::Single && entries.len() == 1;
+    let header = comments.take(&Anchor::Header);
+    let mut output = Vec::new();
+
+    if entries.is_empty() {
+        output.push(with_trailing("builds: []".to_string(), header.trailing));
+        push_comments(&mut output, 2, header.leading);
+    } else {
+        output.push(with_trailing("builds:".to_string(), header.trailing));
+        push_comments(&mut output, 2, header.leading);
+    }
+
+    // A single entry is written at the indentation list entries' keys would
+    // have without the `- `, so both forms share the rendering below.
+    let key_indent = if single { 2 } else { 4 };
+
+    for (index, entry) in entries.into_iter().enumerate() {
+        let entry_comments = comments.take(&Anchor::Entry(index));
+        push_comments(&mut output, 2, entry_comments.leading);
+
+        let mut lines: Vec<(Vec<String>, String)> = Vec::new();
+        for (key, patterns) in &entry.patterns {
+            let (kept, shadowed) = canonical_patterns(patterns);
+            for (from, to) in &shadowed {
+                comments.move_pattern(index, key, from, to);
+            }
+
+            let key_comments = comments.take(&Anchor::Key(index, key.clone()));
+            let mut block = Vec::new();
+            if kept.is_empty() {
+                lines.push((
+                    key_comments.leading,
+                    with_trailing(format!("{}: []", key), key_comments.trailing),
+                ));
+                continue;
+            }
+            for pattern in &kept {
+                let pattern_comments =
+                    comments.take(&Anchor::Pattern(index, key.clone(), pattern.clone()));
+                push_comments(&mut block, key_indent + 2, pattern_comments.leading);
+                block.push(with_trailing(
+                    format!("{}- {}", " ".repeat(key_indent + 2), quote_pattern(pattern)),
+                    pattern_comments.trailing,
+                ));
+            }
+            let mut line = with_trailing(format!("{}:", key), key_comments.trailing);
+            for item in block {
+                line.push('\n');
+                line.push_str(&item);
+            }
+            lines.push((key_comments.leading, line));
+        }
+        for (key, value) in entry.scalars.iter().chain(&entry.other) {
+            let key_comments = comments.take(&Anchor::Key(index, key.clone()));
+            lines.push((
+                key_comments.leading,
+                with_trailing(
+                    format!("{}: {}", key, render_value(value)),
+                    key_comments.trailing,
+                ),
+            ));
+        }
+
+        let entry_end = comments.take(&Anchor::EntryEnd(index));
+        if lines.is_empty() {
+            output.push(with_trailing(
+                if single { "  {}" } else { "  - {}" }.to_string(),
+                entry_comments.trailing,
+            ));
+            push_comments(&mut output, key_indent, entry_end.leading);
+            continue;
+        }
+
+        for (position, (leading, line)) in lines.into_iter().enumerate() {
+            let first_in_list = !single && position == 0;
+            push_comments(
+                &mut output,
+                if first_in_list { 2 } else { key_indent },
+                leading,
+            );
+            let prefix = if first_in_list {
+                "  - ".to_string()
+            } else {
+                " ".repeat(key_indent)
+            };
+            let line = format!("{}{}", prefix, line);
+            output.push(if position == 0 {
+                with_trailing(line, entry_comments.trailing.clone())
+            } else {
+                line
+            });
+        }
+        push_comments(&mut output, key_indent, entry_end.leading);
+    }
+
+    // Anything left over was attached to a pattern or key that no longer
+    // exists, keep it rather than silently dropping it.
+    let mut leftovers: Vec<(Anchor, Comments)> = comments.comments.drain().collect();
+    leftovers.sort_by_key(|(anchor, _)| format!("{:?}", anchor));
+    for (_, leftover) in leftovers {
+        push_comments(&mut output, 2, leftover.leading);
+        push_comments(&mut output, 2, leftover.trailing.into_iter().collect());
+    }
+    push_comments(&mut output, 2, comments.dangling);
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(contents: &str, style: BuildsStyle) -> String {
+        format_config(contents, "garnix.yaml", style).unwrap()
+    }
+
+    #[test]
+    fn test_canonical_patterns() {
+        let patterns: Vec<String> = [
+            "packages.x86_64-linux.hello",
+            "checks.*.*",
+            "packages.*.*",
+            "checks.*.*",
+            "devShells.*",
+        ]
+        .iter()
+        .map(|pattern| pattern.to_string())
+        .collect();
+
+        let (kept, shadowed) = canonical_patterns(&patterns);
+        assert_eq!(kept, vec!["checks.*.*", "devShells.*", "packages.*.*"]);
+        assert_eq!(
+            shadowed,
+            vec![(
+                "packages.x86_64-linux.hello".to_string(),
+                "packages.*.*".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_single_to_list_keeps_comments() {
+        let input = r#"# top comment
+builds: # builds comment
+  # what we build
+  include: ["packages.*.*", "checks.*.*"] # everything
+  branch: main
+  exclude:
+    # broken upstream
+    - packages.x86_64-linux.broken
+incrementalizeBuilds: true # left alone
+"#;
+        let expected = r#"# top comment
+builds: # builds comment
+  # what we build
+  - include: # everything
+      - "checks.*.*"
+      - "packages.*.*"
+    exclude:
+      # broken upstream
+      - "packages.x86_64-linux.broken"
+    branch: main
+incrementalizeBuilds: true # left alone
+"#;
+        let formatted = format(input, BuildsStyle::List);
+        assert_eq!(formatted, expected);
+        assert_eq!(format(&formatted, BuildsStyle::List), formatted);
+    }
+
+    #[test]
+    fn test_list_to_single() {
+        let input = "builds:\n  - include:\n      - \"checks.*.*\"\n    timeout: 30m\n";
+        assert_eq!(
+            format(input, BuildsStyle::Single),
+            "builds:\n  include:\n    - \"checks.*.*\"\n  timeout: 30m\n"
+        );
+    }
+
+    #[test]
+    fn test_multiple_entries_stay_a_list() {
+        let input = r#"builds:
+  - include: ["packages.*.*"]
+  # second entry
+  - include:
+      - checks.x86_64-linux.fmt
+      - checks.*.* # all checks
+    exclude: []
+"#;
+        let formatted = format(input, BuildsStyle::Single);
+        assert_eq!(
+            formatted,
+            r#"builds:
+  - include:
+      - "packages.*.*"
+  # second entry
+  - include:
+      - "checks.*.*" # all checks
+    exclude: []
+"#
+        );
+    }
+
+    #[test]
+    fn test_comments_at_the_end_of_an_entry_stay_with_it() {
+        let input = r#"builds:
+  - include:
+      - "checks.*.*"
+    # applies to all branches
+  # nightly only
+  - include:
+      - "packages.*.*"
+    branch: nightly
+    # keep in sync with CI
+"#;
+        assert_eq!(format(input, BuildsStyle::List), input);
+    }
+
+    #[test]
+    fn test_shadowed_pattern_comments_move_to_broader_pattern() {
+        let input = "builds:\n  include:\n    # the one we care about\n    - packages.*.hello\n    - \"packages.*.*\"\n";
+        assert_eq!(
+            format(input, BuildsStyle::List),
+            "builds:\n  - include:\n      # the one we care about\n      - \"packages.*.*\"\n"
+        );
+    }
+
+    #[test]
+    fn test_entry_without_include_keeps_defaults() {
+        let input = "builds:\n  - exclude: [\"checks.*.*\"]\n    branch: main\n";
+        assert_eq!(
+            format(input, BuildsStyle::List),
+            "builds:\n  - exclude:\n      - \"checks.*.*\"\n    branch: main\n"
+        );
+    }
+
+    #[test]
+    fn test_without_builds_is_untouched() {
+        let input = "# nothing here\nincrementalizeBuilds: false\n";
+        assert_eq!(format(input, BuildsStyle::List), input);
+    }
+
+    #[test]
+    fn test_split_comment() {
+        assert_eq!(
+            split_comment("  - \"a#b\" # c"),
+            ("  - \"a#b\"", Some("# c".to_string()))
+        );
+        assert_eq!(split_comment("branch: fix#1"), ("branch: fix#1", None));
+    }
+}
@@ -0,0 +1,310 @@
+use crate::error::{GarnixError, Result};
+
+/// What to do with `${VAR}` when `VAR` is unset and has no default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnsetVariables {
+    /// Fail with a `GarnixError::Config` naming the key, for `run` and `list`.
+    Error,
+    /// Leave the reference as written, for read-only commands and old
+    /// revisions that may expect variables nobody sets anymore.
+    Keep,
+}
+
+/// Where and how references are substituted.
+struct Context<'a> {
+    file: &'a str,
+    unset: UnsetVariables,
+    lookup: &'a dyn Fn(&str) -> Option<String>,
+}
+
+/// Substitutes `${VAR}` and `${VAR:-default}` in every string value of a
+/// YAML document. `$$` is a literal `$`. Keys are left alone. Returns
+/// whether any value contained a reference.
+pub fn interpolate_yaml(
+    value: &mut serde_yaml::Value,
+    file: &str,
+    unset: UnsetVariables,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<bool> {
+    let context = Context {
+        file,
+        unset,
+        lookup,
+    };
+    interpolate_yaml_at(value, "", &context)
+}
+
+fn interpolate_yaml_at(
+    value: &mut serde_yaml::Value,
+    path: &str,
+    context: &Context,
+) -> Result<bool> {
+    let mut found = false;
+    match value {
+        serde_yaml::Value::String(string) => {
+            if has_references(string) {
+                *string = interpolate_str(string, path, context)?;
+                found = true;
+            }
+        }
+        serde_yaml::Value::Sequence(items) => {
+            for (index, item) in items.iter_mut().enumerate() {
+                found |= interpolate_yaml_at(item, &format!("{}[{}]", path, index), context)?;
+            }
+        }
+        serde_yaml::Value::Mapping(mapping) => {
+            for (key, item) in mapping.iter_mut() {
+                let key = match key {
+                    serde_yaml::Value::String(key) => key.clone(),
+                    other => serde_yaml::to_string(other)
+                        .unwrap_or_default()
+                        .trim()
+                        .to_string(),
+                };
+                found |= interpolate_yaml_at(item, &join(path, &key), context)?;
+            }
+        }
+        serde_yaml::Value::Tagged(tagged) => {
+            found |= interpolate_yaml_at(&mut tagged.value, path, context)?;
+        }
+        serde_yaml::Value::Null | serde_yaml::Value::Bool(_) | serde_yaml::Value::Number(_) => {}
+    }
+    Ok(found)
+}
+
+/// Same as `interpolate_yaml`, for garnix.json.
+pub fn interpolate_json(
+    value: &mut serde_json::Value,
+    file: &str,
+    unset: UnsetVariables,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<bool> {
+    let context = Context {
+        file,
+        unset,
+        lookup,
+    };
+    interpolate_json_at(value, "", &context)
+}
+
+fn interpolate_json_at(
+    value: &mut serde_json::Value,
+    path: &str,
+    context: &Context,
+) -> Result<bool> {
+    let mut found = false;
+    match value {
+        serde_json::Value::String(string) => {
+            if has_references(string) {
+                *string = interpolate_str(string, path, context)?;
+                found = true;
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for (index, item) in items.iter_mut().enumerate() {
+                found |= interpolate_json_at(item, &format!("{}[{}]", path, index), context)?;
+            }
+        }
+        serde_json::Value::Object(object) => {
+            for (key, item) in object.iter_mut() {
+                found |= interpolate_json_at(item, &join(path, key), context)?;
+            }
+        }
+        serde_json::Value::Null | serde_json::Value::Bool(_) | serde_json::Value::Number(_) => {}
+    }
+    Ok(found)
+}
+
+/// Whether a value needs substituting: `$` on its own is left alone.
+fn has_references(value: &str) -> bool {
+    value.contains("${") || value.contains("$$")
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+/// Substitutes the references in one value found at key `path`.
+fn interpolate_str(value: &str, path: &str, context: &Context) -> Result<String> {
+    let file = context.file;
+    let mut result = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+
+        if let Some(after_escape) = after.strip_prefix('$') {
+            result.push('$');
+            rest = after_escape;
+            continue;
+        }
+        let Some(reference) = after.strip_prefix('{') else {
+            result.push('$');
+            rest = after;
+            continue;
+        };
+
+        let end = reference.find('}').ok_or_else(|| {
+            GarnixError::Config(format!(
+                "{}: unterminated `${{` in `{}` at {}",
+                file, value, path
+            ))
+        })?;
+        let (name, default) = match reference[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&reference[..end], None),
+        };
+        if !is_variable_name(name) {
+            return Err(GarnixError::Config(format!(
+                "{}: invalid variable reference `${{{}}}` at {}",
+                file,
+                &reference[..end],
+                path
+            )));
+        }
+
+        // Like the shell, `:-` also replaces a variable that is set but empty.
+        match ((context.lookup)(name), default) {
+            (Some(set), Some(default)) if set.is_empty() => result.push_str(default),
+            (Some(set), _) => result.push_str(&set),
+            (None, Some(default)) => result.push_str(default),
+            (None, None) if context.unset == UnsetVariables::Keep => {
+                result.push_str(&format!("${{{}}}", name));
+            }
+            (None, None) => {
+                return Err(GarnixError::Config(format!(
+                    "{}: environment variable {} used at {} is not set, \
+                     set it or give a default with ${{{}:-default}}",
+                    file, name, path, name
+                )));
+            }
+        }
+        rest = &reference[end + 1..];
+    }
+
+    result.push_str(rest);
+    Ok(result)
+}
+
+fn is_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|first| first == '_' || first.is_ascii_alphabetic())
+        && chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "SYSTEM" => Some("aarch64-linux".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    fn context(unset: UnsetVariables) -> Context<'static> {
+        Context {
+            file: "garnix.yaml",
+            unset,
+            lookup: &lookup,
+        }
+    }
+
+    #[test]
+    fn test_interpolate_str() {
+        let cases = [
+            ("packages.${SYSTEM}.*", "packages.aarch64-linux.*"),
+            ("${UNSET:-main}", "main"),
+            ("${SYSTEM:-x86_64-linux}", "aarch64-linux"),
+            ("${EMPTY:-fallback}", "fallback"),
+            ("${EMPTY}", ""),
+            ("${UNSET:-}", ""),
+            ("cost: $$5, $HOME", "cost: $5, $HOME"),
+            ("${SYSTEM}-${SYSTEM}", "aarch64-linux-aarch64-linux"),
+            ("no references", "no references"),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(
+                interpolate_str(input, "key", &context(UnsetVariables::Error)).unwrap(),
+                expected,
+                "input: {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_invalid_references() {
+        for input in ["${UNSET}", "${SYSTEM", "${}", "${1X}"] {
+            let error = interpolate_str(input, "key", &context(UnsetVariables::Error)).unwrap_err();
+            assert!(matches!(error, GarnixError::Config(_)), "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_keep_unset_variables() {
+        let keep = context(UnsetVariables::Keep);
+        assert_eq!(
+            interpolate_str("${UNSET}.${SYSTEM}", "key", &keep).unwrap(),
+            "${UNSET}.aarch64-linux"
+        );
+        assert_eq!(
+            interpolate_str("${UNSET:-main}", "key", &keep).unwrap(),
+            "main"
+        );
+        // Malformed references are still errors.
+        assert!(interpolate_str("${SYSTEM", "key", &keep).is_err());
+    }
+
+    #[test]
+    fn test_unset_variable_names_key_path() {
+        let mut yaml: serde_yaml::Value = serde_yaml::from_str(
+            "builds:\n  - include: [\"packages.${SYSTEM}.*\"]\n  - branch: ${BRANCH}\n",
+        )
+        .unwrap();
+        let error =
+            interpolate_yaml(&mut yaml, "garnix.yaml", UnsetVariables::Error, &lookup).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Configuration error: garnix.yaml: environment variable BRANCH used at \
+             builds[1].branch is not set, set it or give a default with ${BRANCH:-default}"
+        );
+
+        let mut json: serde_json::Value =
+            serde_json::from_str(r#"{"servers": [{"configuration": "${HOST}"}]}"#).unwrap();
+        let error =
+            interpolate_json(&mut json, "garnix.json", UnsetVariables::Error, &lookup).unwrap_err();
+        assert!(error.to_string().contains("servers[0].configuration"));
+    }
+
+    #[test]
+    fn test_reports_whether_values_had_references() {
+        let mut plain: serde_yaml::Value =
+            serde_yaml::from_str("# costs ${5}\nbuilds:\n  include: [\"$x.*.*\"]\n").unwrap();
+        assert!(
+            !interpolate_yaml(&mut plain, "garnix.yaml", UnsetVariables::Error, &lookup).unwrap()
+        );
+
+        let mut referencing: serde_json::Value =
+            serde_json::from_str(r#"{"builds": {"branch": "${UNSET:-main}"}}"#).unwrap();
+        assert!(
+            interpolate_json(
+                &mut referencing,
+                "garnix.json",
+                UnsetVariables::Error,
+                &lookup
+            )
+            .unwrap()
+        );
+    }
+}
@@ -0,0 +1,394 @@
+use crate::config::locate::LineMap;
+use crate::config::types::{BuildEntry, BuildsConfig};
+use crate::matcher::{pattern_covers, patterns_overlap};
+use std::fmt;
+
+/// A lint rule. IDs and names are stable, so they can be used with `--allow`
+/// and in CI logs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rule {
+    /// An include that another entry already builds wherever this one applies.
+    CoveredInclude,
+    /// An entry whose excludes remove everything it includes.
+    ExcludeRemovesAll,
+    /// An entry restricted to a branch that doesn't exist in the repository.
+    UnknownBranch,
+    /// A later entry's include building attributes an earlier entry excludes,
+    /// which the exclude doesn't prevent.
+    IncludeAfterExclude,
+    /// A later entry's exclude overlapping what an earlier entry includes,
+    /// which it doesn't remove.
+    ExcludeAfterInclude,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 5] = [
+        Rule::CoveredInclude,
+        Rule::ExcludeRemovesAll,
+        Rule::UnknownBranch,
+        Rule::IncludeAfterExclude,
+        Rule::ExcludeAfterInclude,
+    ];
+
+    pub fn id(self) -> &'static str {
+        match self {
+            Rule::CoveredInclude => "GX001",
+            Rule::ExcludeRemovesAll => "GX002",
+            Rule::UnknownBranch => "GX003",
+            Rule::IncludeAfterExclude => "GX004",
+            Rule::ExcludeAfterInclude => "GX005",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Rule::CoveredInclude => "covered-include",
+            Rule::ExcludeRemovesAll => "exclude-removes-all",
+            Rule::UnknownBranch => "unknown-branch",
+            Rule::IncludeAfterExclude => "include-after-exclude",
+            Rule::ExcludeAfterInclude => "exclude-after-include",
+        }
+    }
+
+    /// Notes point out patterns that behave differently since excludes
+    /// became entry-scoped. The config is fine, so they don't fail lint.
+    pub fn is_note(self) -> bool {
+        matches!(self, Rule::IncludeAfterExclude | Rule::ExcludeAfterInclude)
+    }
+
+    /// Looks a rule up by ID (`GX001`) or name (`covered-include`).
+    pub fn parse(value: &str) -> Option<Rule> {
+        Rule::ALL
+            .into_iter()
+            .find(|rule| rule.id().eq_ignore_ascii_case(value) || rule.name() == value)
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.id(), self.name())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub rule: Rule,
+    /// Index into `BuildsConfig::entries()`.
+    pub entry: usize,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+/// Runs every rule over `builds`. `branches` are the repository's branches;
+/// without them `unknown-branch` is skipped.
+pub fn lint(builds: &BuildsConfig, lines: &LineMap, branches: Option<&[String]>) -> Vec<Finding> {
+    let entries = builds.entries();
+    let mut findings = Vec::new();
+
+    covered_includes(&entries, lines, &mut findings);
+    excludes_removing_all(&entries, lines, &mut findings);
+    if let Some(branches) = branches {
+        unknown_branches(&entries, lines, branches, &mut findings);
+    }
+    includes_after_excludes(&entries, lines, &mut findings);
+    excludes_after_includes(&entries, lines, &mut findings);
+
+    findings.sort_by_key(|finding| (finding.line, finding.entry, finding.rule.id()));
+    findings
+}
+
+/// Whether `broad` applies on every branch `narrow` applies on.
+fn applies_wherever(broad: &BuildEntry, narrow: &BuildEntry) -> bool {
+    broad.branch.is_none() || broad.branch == narrow.branch
+}
+
+/// Whether there's a branch both entries apply on.
+fn apply_together(a: &BuildEntry, b: &BuildEntry) -> bool {
+    a.branch.is_none() || b.branch.is_none() || a.branch == b.branch
+}
+
+fn item_line(lines: &LineMap, entry: usize, key: &str, item: usize) -> Option<usize> {
+    lines
+        .entry_item_line(entry, key, item)
+        .or_else(|| lines.entry_key_line(entry, key))
+        .or_else(|| lines.entry_line(entry))
+}
+
+fn covered_includes(entries: &[&BuildEntry], lines: &LineMap, findings: &mut Vec<Finding>) {
+    for (index, entry) in entries.iter().enumerate() {
+        for (item, pattern) in entry.include.iter().enumerate() {
+            let covering = entries.iter().enumerate().find_map(|(other_index, other)| {
+                // Excludes only apply to their own entry, so an entry that
+                // excludes part of the pattern doesn't build all of it.
+                if other_index == index
+                    || !applies_wherever(other, entry)
+                    || other
+                        .exclude
+                        .iter()
+                        .any(|exclude| patterns_overlap(exclude, pattern))
+                {
+                    return None;
+                }
+                other
+                    .include
+                    .iter()
+                    .find(|broader| {
+                        pattern_covers(broader, pattern)
+                            // Of two identical includes only the later one is redundant.
+                            && (*broader != pattern || other_index < index)
+                    })
+                    .map(|broader| (other_index, broader))
+            });
+
+            if let Some((other_index, broader)) = covering {
+                findings.push(Finding {
+                    rule: Rule::CoveredInclude,
+                    entry: index,
+                    line: item_line(lines, index, "include", item),
+                    message: format!(
+                        "include \"{}\" in entry {} is already built by \"{}\" in entry {}",
+                        pattern,
+                        index + 1,
+                        broader,
+                        other_index + 1
+                    ),
+                });
+            }
+        }
+    }
+}
+
+fn excludes_removing_all(entries: &[&BuildEntry], lines: &LineMap, findings: &mut Vec<Finding>) {
+    for (index, entry) in entries.iter().enumerate() {
+        let removes_all = !entry.include.is_empty()
+            && entry.include.iter().all(|pattern| {
+                entry
+                    .exclude
+                    .iter()
+                    .any(|exclude| pattern_covers(exclude, pattern))
+            });
+
+        if removes_all {
+            findings.push(Finding {
+                rule: Rule::ExcludeRemovesAll,
+                entry: index,
+                line: lines
+                    .entry_key_line(index, "exclude")
+                    .or_else(|| lines.entry_line(index)),
+                message: format!(
+                    "the excludes of entry {} remove everything it includes",
+                    index + 1
+                ),
+            });
+        }
+    }
+}
+
+fn unknown_branches(
+    entries: &[&BuildEntry],
+    lines: &LineMap,
+    branches: &[String],
+    findings: &mut Vec<Finding>,
+) {
+    for (index, entry) in entries.iter().enumerate() {
+        let Some(branch) = &entry.branch else {
+            continue;
+        };
+        // An unset `${VAR}` is kept as written, it may name a real branch
+        // wherever the variable is set.
+        if branches.contains(branch) || branch.contains("${") {
+            continue;
+        }
+
+        findings.push(Finding {
+            rule: Rule::UnknownBranch,
+            entry: index,
+            line: lines
+                .entry_key_line(index, "branch")
+                .or_else(|| lines.entry_line(index)),
+            message: format!(
+                "entry {} only applies to branch \"{}\", which doesn't exist in this repository",
+                index + 1,
+                branch
+            ),
+        });
+    }
+}
+
+fn includes_after_excludes(entries: &[&BuildEntry], lines: &LineMap, findings: &mut Vec<Finding>) {
+    for (index, entry) in entries.iter().enumerate() {
+        for (item, pattern) in entry.include.iter().enumerate() {
+            let readded = entries[..index]
+                .iter()
+                .enumerate()
+                .filter(|(_, earlier)| apply_together(earlier, entry))
+                .find_map(|(earlier_index, earlier)| {
+                    earlier
+                        .exclude
+                        .iter()
+                        .find(|exclude| {
+                            patterns_overlap(exclude, pattern)
+                                // Excluding it again in the same entry keeps it out.
+                                && !entry
+                                    .exclude
+                                    .iter()
+                                    .any(|own| pattern_covers(own, exclude))
+                        })
+                        .map(|exclude| (earlier_index, exclude))
+                });
+
+            if let Some((earlier_index, exclude)) = readded {
+                findings.push(Finding {
+                    rule: Rule::IncludeAfterExclude,
+                    entry: index,
+                    line: item_line(lines, index, "include", item),
+                    message: format!(
+                        "include \"{}\" in entry {} builds attributes entry {} excludes with \"{}\", excludes only apply to their own entry",
+                        pattern,
+                        index + 1,
+                        earlier_index + 1,
+                        exclude
+                    ),
+                });
+            }
+        }
+    }
+}
+
+/// Before excludes were scoped to their entry, these removed attributes an
+/// earlier entry included.
+fn excludes_after_includes(entries: &[&BuildEntry], lines: &LineMap, findings: &mut Vec<Finding>) {
+    for (index, entry) in entries.iter().enumerate() {
+        for (item, exclude) in entry.exclude.iter().enumerate() {
+            let kept = entries[..index]
+                .iter()
+                .enumerate()
+                .filter(|(_, earlier)| apply_together(earlier, entry))
+                .find_map(|(earlier_index, earlier)| {
+                    earlier
+                        .include
+                        .iter()
+                        .find(|include| {
+                            patterns_overlap(include, exclude)
+                                // The earlier entry keeps it out itself.
+                                && !earlier
+                                    .exclude
+                                    .iter()
+                                    .any(|own| pattern_covers(own, exclude))
+                        })
+                        .map(|include| (earlier_index, include))
+                });
+
+            if let Some((earlier_index, include)) = kept {
+                findings.push(Finding {
+                    rule: Rule::ExcludeAfterInclude,
+                    entry: index,
+                    line: item_line(lines, index, "exclude", item),
+                    message: format!(
+                        "exclude \"{}\" in entry {} doesn't remove what entry {} includes with \"{}\", excludes only apply to their own entry",
+                        exclude,
+                        index + 1,
+                        earlier_index + 1,
+                        include
+                    ),
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::parse_config;
+
+    type Reported = Vec<(Rule, Option<usize>)>;
+
+    fn lint_yaml(yaml: &str, branches: &[&str]) -> Reported {
+        let config = parse_config(yaml).unwrap();
+        let branches: Vec<String> = branches.iter().map(|b| b.to_string()).collect();
+        lint(&config.builds, &LineMap::parse(yaml), Some(&branches))
+            .into_iter()
+            .map(|finding| (finding.rule, finding.line))
+            .collect()
+    }
+
+    #[test]
+    fn test_rules() {
+        let cases: &[(&str, Reported)] = &[
+            (
+                "builds:\n  - include: [\"packages.*.*\"]\n  - include: [\"packages.*.hello\"]\n",
+                vec![(Rule::CoveredInclude, Some(3))],
+            ),
+            (
+                // Scoped to another branch, so not covered on main.
+                "builds:\n  - include: [\"packages.*.*\"]\n    branch: main\n  - include: [\"packages.*.hello\"]\n",
+                vec![],
+            ),
+            (
+                // Entry 1 doesn't build packages.*.hello, so entry 2 is needed.
+                "builds:\n  - include: [\"packages.*.*\"]\n    exclude: [\"packages.*.hello\"]\n  - include: [\"packages.*.hello\"]\n",
+                vec![(Rule::IncludeAfterExclude, Some(4))],
+            ),
+            (
+                "builds:\n  - include: [\"checks.*.*\"]\n  - include: [\"checks.*.*\"]\n",
+                vec![(Rule::CoveredInclude, Some(3))],
+            ),
+            (
+                "builds:\n  include: [\"packages.*.hello\"]\n  exclude: [\"packages.*.*\"]\n",
+                vec![(Rule::ExcludeRemovesAll, Some(3))],
+            ),
+            (
+                "builds:\n  include: [\"packages.*.*\"]\n  branch: mian\n",
+                vec![(Rule::UnknownBranch, Some(3))],
+            ),
+            (
+                "builds:\n  include: [\"packages.*.*\"]\n  branch: ${RELEASE_BRANCH}\n",
+                vec![],
+            ),
+            (
+                "builds:\n  - exclude: [\"packages.*.slow\"]\n  - include:\n      - \"checks.*.*\"\n      - \"packages.*.*\"\n",
+                vec![(Rule::IncludeAfterExclude, Some(5))],
+            ),
+            (
+                "builds:\n  - exclude: [\"packages.*.slow\"]\n  - include: [\"packages.*.*\"]\n    exclude: [\"packages.*.slow\"]\n",
+                vec![],
+            ),
+            (
+                "builds:\n  - include: [\"packages.*.*\"]\n    branch: main\n  - include: [\"checks.*.*\"]\n",
+                vec![],
+            ),
+            (
+                "builds:\n  - include: [\"packages.*.*\"]\n  - include: [\"checks.*.*\"]\n    exclude: [\"*.*.slow\"]\n",
+                vec![(Rule::ExcludeAfterInclude, Some(4))],
+            ),
+            (
+                // Entry 1 already keeps packages.*.slow out.
+                "builds:\n  - include: [\"packages.*.*\"]\n    exclude: [\"packages.*.slow\"]\n  - include: [\"checks.*.*\"]\n    exclude: [\"packages.*.slow\"]\n",
+                vec![],
+            ),
+        ];
+
+        for (yaml, expected) in cases {
+            assert_eq!(&lint_yaml(yaml, &["main"]), expected, "config:\n{}", yaml);
+        }
+    }
+
+    #[test]
+    fn test_unknown_branch_needs_branches() {
+        let yaml = "builds:\n  include: [\"packages.*.*\"]\n  branch: mian\n";
+        let config = parse_config(yaml).unwrap();
+        assert!(lint(&config.builds, &LineMap::parse(yaml), None).is_empty());
+    }
+
+    #[test]
+    fn test_rule_ids_are_stable() {
+        let ids: Vec<&str> = Rule::ALL.iter().map(|rule| rule.id()).collect();
+        assert_eq!(ids, vec!["GX001", "GX002", "GX003", "GX004", "GX005"]);
+        assert_eq!(Rule::parse("gx002"), Some(Rule::ExcludeRemovesAll));
+        assert_eq!(Rule::parse("unknown-branch"), Some(Rule::UnknownBranch));
+        assert_eq!(Rule::parse("nope"), None);
+        assert!(Rule::ExcludeAfterInclude.is_note());
+        assert!(!Rule::CoveredInclude.is_note());
+    }
+}
@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+use yaml_rust2::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust2::scanner::Marker;
+
+/// Line numbers of the keys and list items in a YAML (or JSON) document,
+/// addressed by paths like `builds[1].include`.
+///
+/// serde_yaml only has locations for errors, so lint findings and
+/// `config show --effective` sources come from a second pass with
+/// yaml-rust2's event parser, which marks every node. The tests below check
+/// that both parsers see the same keys.
+#[derive(Debug, Default)]
+pub struct LineMap {
+    lines: HashMap<String, usize>,
+}
+
+impl LineMap {
+    /// Maps every key in `contents`. A document that doesn't parse gives an
+    /// empty map; serde reports the actual error.
+    pub fn parse(contents: &str) -> Self {
+        let mut recorder = Recorder::default();
+        let _ = Parser::new_from_str(contents).load(&mut recorder, false);
+        LineMap {
+            lines: recorder.lines,
+        }
+    }
+
+    pub fn line(&self, path: &str) -> Option<usize> {
+        self.lines.get(path).copied()
+    }
+
+    /// Line of build entry `index`, which is `builds` itself when the file
+    /// uses the single entry form.
+    pub fn entry_line(&self, index: usize) -> Option<usize> {
+        self.line(&format!("builds[{}]", index))
+            .or_else(|| self.single_entry(index).and_then(|_| self.line("builds")))
+    }
+
+    /// Line of `key` in build entry `index`, if the file sets it.
+    pub fn entry_key_line(&self, index: usize, key: &str) -> Option<usize> {
+        self.line(&format!("builds[{}].{}", index, key))
+            .or_else(|| {
+                self.single_entry(index)
+                    .and_then(|_| self.line(&format!("builds.{}", key)))
+            })
+    }
+
+    /// Line of the `item`th pattern under `key` in build entry `index`.
+    pub fn entry_item_line(&self, index: usize, key: &str, item: usize) -> Option<usize> {
+        self.line(&format!("builds[{}].{}[{}]", index, key, item))
+            .or_else(|| {
+                self.single_entry(index)
+                    .and_then(|_| self.line(&format!("builds.{}[{}]", key, item)))
+            })
+    }
+
+    fn single_entry(&self, index: usize) -> Option<()> {
+        (index == 0 && self.line("builds[0]").is_none()).then_some(())
+    }
+}
+
+enum Frame {
+    Mapping { path: String, key: Option<String> },
+    Sequence { path: String, index: usize },
+}
+
+#[derive(Default)]
+struct Recorder {
+    stack: Vec<Frame>,
+    lines: HashMap<String, usize>,
+}
+
+impl Recorder {
+    /// The path of the node that's starting, recording its line for list items.
+    fn node_path(&mut self, line: usize) -> String {
+        match self.stack.last() {
+            None => String::new(),
+            Some(Frame::Mapping { path, key }) => join(path, key.as_deref().unwrap_or_default()),
+            Some(Frame::Sequence { path, index }) => {
+                let path = format!("{}[{}]", path, index);
+                self.lines.entry(path.clone()).or_insert(line);
+                path
+            }
+        }
+    }
+
+    fn finish_node(&mut self) {
+        match self.stack.last_mut() {
+            Some(Frame::Mapping { key, .. }) => *key = None,
+            Some(Frame::Sequence { index, .. }) => *index += 1,
+            None => {}
+        }
+    }
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+impl MarkedEventReceiver for Recorder {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        let line = mark.line();
+        match event {
+            Event::Scalar(value, ..) => {
+                if let Some(Frame::Mapping {
+                    path,
+                    key: key @ None,
+                }) = self.stack.last_mut()
+                {
+                    self.lines.entry(join(path, &value)).or_insert(line);
+                    *key = Some(value);
+                    return;
+                }
+                self.node_path(line);
+                self.finish_node();
+            }
+            Event::Alias(_) => {
+                self.node_path(line);
+                self.finish_node();
+            }
+            Event::MappingStart(..) => {
+                let path = self.node_path(line);
+                self.stack.push(Frame::Mapping { path, key: None });
+            }
+            Event::SequenceStart(..) => {
+                let path = self.node_path(line);
+                self.stack.push(Frame::Sequence { path, index: 0 });
+            }
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.stack.pop();
+                self.finish_node();
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_yaml_lines() {
+        let lines = LineMap::parse(
+            r#"# comment
+builds:
+  - include:
+      - "packages.*.*"
+    branch: main
+  - exclude: ["checks.*.*"]
+incrementalizeBuilds: true
+"#,
+        );
+
+        assert_eq!(lines.line("builds"), Some(2));
+        assert_eq!(lines.entry_line(0), Some(3));
+        assert_eq!(lines.entry_key_line(0, "include"), Some(3));
+        assert_eq!(lines.line("builds[0].include[0]"), Some(4));
+        assert_eq!(lines.entry_key_line(0, "branch"), Some(5));
+        assert_eq!(lines.entry_key_line(1, "exclude"), Some(6));
+        assert_eq!(lines.entry_key_line(1, "include"), None);
+        assert_eq!(lines.entry_item_line(1, "exclude", 0), Some(6));
+        assert_eq!(lines.line("incrementalizeBuilds"), Some(7));
+    }
+
+    /// Every key and list item path in a document, as serde_yaml sees it.
+    fn serde_paths(value: &serde_yaml::Value, path: &str, paths: &mut Vec<String>) {
+        match value {
+            serde_yaml::Value::Mapping(mapping) => {
+                for (key, item) in mapping {
+                    let path = join(path, key.as_str().unwrap());
+                    paths.push(path.clone());
+                    serde_paths(item, &path, paths);
+                }
+            }
+            serde_yaml::Value::Sequence(items) => {
+                for (index, item) in items.iter().enumerate() {
+                    let path = format!("{}[{}]", path, index);
+                    paths.push(path.clone());
+                    serde_paths(item, &path, paths);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn test_fixtures_match_serde_yaml() {
+        for fixture in [
+            include_str!("../../tests/fixtures/sample_garnix.yaml"),
+            include_str!("../../tests/fixtures/sample_garnix.json"),
+        ] {
+            let value: serde_yaml::Value = serde_yaml::from_str(fixture).unwrap();
+            let mut paths = Vec::new();
+            serde_paths(&value, "", &mut paths);
+            assert!(paths.len() > 10);
+
+            let lines = LineMap::parse(fixture);
+            for path in &paths {
+                assert!(lines.line(path).is_some(), "no line for {}", path);
+            }
+            assert_eq!(lines.lines.len(), paths.len());
+        }
+    }
+
+    #[test]
+    fn test_anchors_and_aliases() {
+        let yaml = "x-packages: &packages\n  - \"packages.*.*\"\nbuilds:\n  - include: *packages\n    branch: main\n  - include: *packages\n";
+        let value: serde_yaml::Value = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(value["builds"][1]["include"][0], "packages.*.*");
+
+        // An alias is one node: its items are where the anchor is.
+        let lines = LineMap::parse(yaml);
+        assert_eq!(lines.line("x-packages[0]"), Some(2));
+        assert_eq!(lines.entry_key_line(0, "include"), Some(4));
+        assert_eq!(lines.entry_key_line(1, "include"), Some(6));
+        assert_eq!(lines.line("builds[1].include[0]"), None);
+    }
+
+    #[test]
+    fn test_single_entry_and_json() {
+        let yaml = LineMap::parse("builds:\n  include: [\"a.b\"]\n  timeout: 30m\n");
+        assert_eq!(yaml.entry_line(0), Some(1));
+        assert_eq!(yaml.entry_key_line(0, "timeout"), Some(3));
+        assert_eq!(yaml.entry_line(1), None);
+
+        let json = LineMap::parse("{\n  \"builds\": [\n    {\"branch\": \"main\"}\n  ]\n}\n");
+        assert_eq!(json.entry_line(0), Some(3));
+        assert_eq!(json.entry_key_line(0, "branch"), Some(3));
+    }
+}
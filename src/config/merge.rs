@@ -0,0 +1,143 @@
+use crate::config::types::{BuildsConfig, GarnixConfig, IncrementalizeBuilds, ServerConfig};
+use serde::Deserialize;
+
+/// A partial config layered on top of garnix.yaml, read from `garnix.local.yaml`
+/// or a `--config` file.
+///
+/// Merge semantics per field:
+/// - `builds`: entries are appended after the base entries, or replace them
+///   entirely with `buildsMerge: replace`
+/// - `incrementalizeBuilds`: replaces the base value when set
+/// - `servers`: replaces the base list when set
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct ConfigOverride {
+    pub builds: Option<BuildsConfig>,
+
+    #[serde(default, rename = "buildsMerge")]
+    pub builds_merge: MergeMode,
+
+    #[serde(rename = "incrementalizeBuilds")]
+    pub incrementalize_builds: Option<IncrementalizeBuilds>,
+
+    pub servers: Option<Vec<ServerConfig>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeMode {
+    #[default]
+    Append,
+    Replace,
+}
+
+impl GarnixConfig {
+    pub fn merge(self, overlay: ConfigOverride) -> GarnixConfig {
+        let builds = match (overlay.builds, overlay.builds_merge) {
+            (None, _) => self.builds,
+            (Some(builds), MergeMode::Replace) => builds,
+            (Some(builds), MergeMode::Append) => {
+                let mut entries: Vec<_> = self.builds.entries().into_iter().cloned().collect();
+                entries.extend(builds.entries().into_iter().cloned());
+                BuildsConfig::Multiple(entries)
+            }
+        };
+
+        GarnixConfig {
+            builds,
+            incrementalize_builds: overlay
+                .incrementalize_builds
+                .unwrap_or(self.incrementalize_builds),
+            servers: overlay.servers.unwrap_or(self.servers),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::parse_config;
+
+    fn base() -> GarnixConfig {
+        parse_config(
+            r#"
+builds:
+  - include: ["packages.*.*"]
+    exclude: ["packages.*.broken"]
+incrementalizeBuilds: false
+servers:
+  - configuration: web
+    deployment:
+      type: on-pull-request
+"#,
+        )
+        .unwrap()
+    }
+
+    fn overlay(yaml: &str) -> ConfigOverride {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn includes(config: &GarnixConfig) -> Vec<Vec<String>> {
+        config
+            .builds
+            .entries()
+            .iter()
+            .map(|entry| entry.include.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_merge_builds() {
+        let cases = [
+            ("{}", vec![vec!["packages.*.*"]]),
+            (
+                "builds:\n  - include: [\"checks.*.*\"]\n",
+                vec![vec!["packages.*.*"], vec!["checks.*.*"]],
+            ),
+            (
+                "builds:\n  include: [\"checks.*.*\"]\n",
+                vec![vec!["packages.*.*"], vec!["checks.*.*"]],
+            ),
+            (
+                "buildsMerge: replace\nbuilds:\n  - include: [\"checks.*.*\"]\n",
+                vec![vec!["checks.*.*"]],
+            ),
+            ("buildsMerge: replace\n", vec![vec!["packages.*.*"]]),
+        ];
+
+        for (yaml, expected) in cases {
+            let merged = base().merge(overlay(yaml));
+            assert_eq!(includes(&merged), expected, "overlay: {}", yaml);
+        }
+    }
+
+    #[test]
+    fn test_merge_replaces_scalars_and_servers() {
+        let merged = base().merge(overlay("incrementalizeBuilds: true\nservers: []\n"));
+        assert!(matches!(
+            merged.incrementalize_builds,
+            IncrementalizeBuilds::Boolean(true)
+        ));
+        assert!(merged.servers.is_empty());
+
+        let untouched = base().merge(overlay("builds:\n  - include: [\"checks.*.*\"]\n"));
+        assert!(matches!(
+            untouched.incrementalize_builds,
+            IncrementalizeBuilds::Boolean(false)
+        ));
+        assert_eq!(untouched.servers.len(), 1);
+    }
+
+    #[test]
+    fn test_merge_keeps_timeouts_from_overlay_entries() {
+        let merged = base().merge(overlay(
+            "builds:\n  - include: [\"checks.*.slow\"]\n    timeout: 2h\n",
+        ));
+        let entries = merged.builds.entries();
+        assert_eq!(entries[0].timeout, None);
+        assert_eq!(
+            entries[1].timeout,
+            Some(std::time::Duration::from_secs(7200))
+        );
+    }
+}
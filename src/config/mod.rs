@@ -1,5 +1,14 @@
+pub mod effective;
+pub mod fmt;
+pub mod interpolate;
+pub mod lint;
+pub mod locate;
+pub mod merge;
 pub mod parser;
+pub mod schema;
 pub mod types;
+pub mod validate;
 
+pub use merge::*;
 pub use parser::*;
 pub use types::*;
@@ -1,21 +1,470 @@
+use crate::config::interpolate::{self, UnsetVariables};
+use crate::config::merge::ConfigOverride;
 use crate::config::types::GarnixConfig;
-use crate::error::Result;
-use std::path::Path;
+use crate::error::{GarnixError, Result};
+use crate::git;
+use serde::de::DeserializeOwned;
+use std::path::{Path, PathBuf};
 
-pub fn load_config<P: AsRef<Path>>(path: P) -> Result<Option<GarnixConfig>> {
-    let path = path.as_ref();
+pub const CONFIG_FILE_NAME: &str = "garnix.yaml";
+
+/// Every file name garnix looks for at the repository root. At most one of
+/// them may exist.
+pub const CONFIG_FILE_NAMES: [&str; 4] = [
+    CONFIG_FILE_NAME,
+    "garnix.yml",
+    ".garnix.yaml",
+    "garnix.json",
+];
+
+/// Untracked per-developer overrides, merged on top of garnix.yaml.
+pub const LOCAL_CONFIG_FILE_NAME: &str = "garnix.local.yaml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Yaml,
+    Json,
+}
+
+impl ConfigFormat {
+    /// `.json` files are JSON, anything else is YAML.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        match path.as_ref().extension() {
+            Some(extension) if extension.eq_ignore_ascii_case("json") => ConfigFormat::Json,
+            _ => ConfigFormat::Yaml,
+        }
+    }
+}
+
+/// A config merged from garnix.yaml and any override files.
+#[derive(Debug, Clone, Default)]
+pub struct LoadedConfig {
+    pub config: Option<GarnixConfig>,
+    /// Files that contributed to `config`, in the order they were applied.
+    pub sources: Vec<PathBuf>,
+}
+
+/// Parses garnix.yaml contents as written: unlike the `load_*` functions,
+/// `${VAR}` references are not substituted.
+pub fn parse_config(contents: &str) -> Result<GarnixConfig> {
+    let config: GarnixConfig = serde_yaml::from_str(contents)?;
+    Ok(config)
+}
+
+/// Parses `contents` as `format`, labelling errors with `file` and
+/// substituting environment variables in string values.
+fn parse_as<T: DeserializeOwned>(
+    contents: &str,
+    format: ConfigFormat,
+    file: &str,
+    unset: UnsetVariables,
+) -> Result<T> {
+    parse_with_env(contents, format, file, unset, &|name| {
+        std::env::var(name).ok()
+    })
+}
+
+/// Documents without references are deserialized from `contents` rather
+/// than the substituted value, which keeps line numbers in type errors.
+fn parse_with_env<T: DeserializeOwned>(
+    contents: &str,
+    format: ConfigFormat,
+    file: &str,
+    unset: UnsetVariables,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<T> {
+    match format {
+        ConfigFormat::Yaml => {
+            let mut value: serde_yaml::Value =
+                serde_yaml::from_str(contents).map_err(|e| GarnixError::invalid_config(file, e))?;
+            if interpolate::interpolate_yaml(&mut value, file, unset, lookup)? {
+                serde_yaml::from_value(value)
+            } else {
+                serde_yaml::from_str(contents)
+            }
+            .map_err(|e| GarnixError::invalid_config(file, e))
+        }
+        ConfigFormat::Json => {
+            let mut value: serde_json::Value = serde_json::from_str(contents)
+                .map_err(|e| GarnixError::invalid_json_config(file, e))?;
+            if interpolate::interpolate_json(&mut value, file, unset, lookup)? {
+                serde_json::from_value(value)
+            } else {
+                serde_json::from_str(contents)
+            }
+            .map_err(|e| GarnixError::invalid_json_config(file, e))
+        }
+    }
+}
 
+/// Reads a single file for inspection, keeping unset `${VAR}` references.
+fn read_config_file<T: DeserializeOwned>(path: &Path) -> Result<Option<T>> {
     if !path.exists() {
         return Ok(None);
     }
 
     let contents = std::fs::read_to_string(path)?;
-    let config: GarnixConfig = serde_yaml::from_str(&contents)?;
+    let value = parse_as(
+        &contents,
+        ConfigFormat::from_path(path),
+        &path.display().to_string(),
+        UnsetVariables::Keep,
+    )?;
+
+    Ok(Some(value))
+}
+
+pub fn load_config<P: AsRef<Path>>(path: P) -> Result<Option<GarnixConfig>> {
+    read_config_file(path.as_ref())
+}
 
-    Ok(Some(config))
+pub fn load_override<P: AsRef<Path>>(path: P) -> Result<Option<ConfigOverride>> {
+    read_config_file(path.as_ref())
+}
+
+/// Picks the single config file name out of those that exist.
+fn single_config_file<'a>(found: Vec<&'a str>, location: &str) -> Result<Option<&'a str>> {
+    match found.as_slice() {
+        [] => Ok(None),
+        [name] => Ok(Some(name)),
+        names => Err(GarnixError::Config(format!(
+            "found multiple config files {}: {}, keep only one",
+            location,
+            names.join(", ")
+        ))),
+    }
+}
+
+/// Finds the config file at the repository root, if any.
+pub fn find_config_file<P: AsRef<Path>>(git_root: P) -> Result<Option<PathBuf>> {
+    let git_root = git_root.as_ref();
+    let found = CONFIG_FILE_NAMES
+        .into_iter()
+        .filter(|name| git_root.join(name).exists())
+        .collect();
+
+    Ok(single_config_file(found, "in the repository root")?.map(|name| git_root.join(name)))
 }
 
 pub fn load_config_from_git_root<P: AsRef<Path>>(git_root: P) -> Result<Option<GarnixConfig>> {
-    let config_path = git_root.as_ref().join("garnix.yaml");
-    load_config(config_path)
+    match find_config_file(git_root)? {
+        Some(config_path) => load_config(config_path),
+        None => Ok(None),
+    }
+}
+
+/// Which kind of file a config layer was read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayerKind {
+    /// The repository's garnix.yaml (or one of its alternate names).
+    Config,
+    /// garnix.local.yaml
+    LocalOverride,
+    /// A `--config` file.
+    Flag,
+}
+
+/// One config file, in the order layers are applied.
+#[derive(Debug, Clone)]
+pub struct ConfigLayer {
+    pub kind: LayerKind,
+    pub path: PathBuf,
+    pub contents: String,
+}
+
+impl ConfigLayer {
+    fn read(kind: LayerKind, path: PathBuf) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let contents = std::fs::read_to_string(&path)?;
+        Ok(Some(ConfigLayer {
+            kind,
+            path,
+            contents,
+        }))
+    }
+
+    /// Parses the layer as a full config or as a `ConfigOverride`.
+    pub fn parse<T: DeserializeOwned>(&self, unset: UnsetVariables) -> Result<T> {
+        parse_as(
+            &self.contents,
+            ConfigFormat::from_path(&self.path),
+            &self.path.display().to_string(),
+            unset,
+        )
+    }
+}
+
+/// Reads the repository's config file, garnix.local.yaml (if present) and
+/// each `--config` file, in the order they apply. Unlike garnix.local.yaml,
+/// an explicit override file that doesn't exist is an error.
+pub fn read_layers<P: AsRef<Path>>(git_root: P, overrides: &[PathBuf]) -> Result<Vec<ConfigLayer>> {
+    let git_root = git_root.as_ref();
+    let mut layers = Vec::new();
+
+    if let Some(base_path) = find_config_file(git_root)? {
+        layers.extend(ConfigLayer::read(LayerKind::Config, base_path)?);
+    }
+    layers.extend(ConfigLayer::read(
+        LayerKind::LocalOverride,
+        git_root.join(LOCAL_CONFIG_FILE_NAME),
+    )?);
+    for path in overrides {
+        let layer = ConfigLayer::read(LayerKind::Flag, path.clone())?.ok_or_else(|| {
+            GarnixError::Config(format!("config file not found: {}", path.display()))
+        })?;
+        layers.push(layer);
+    }
+
+    Ok(layers)
+}
+
+/// Loads the repository's config file and merges its override layers on top.
+/// This is the config that gets built, so an unset variable is an error.
+pub fn load_layered_config<P: AsRef<Path>>(
+    git_root: P,
+    overrides: &[PathBuf],
+) -> Result<LoadedConfig> {
+    let mut loaded = LoadedConfig::default();
+
+    for layer in read_layers(git_root, overrides)? {
+        loaded.config = Some(match layer.kind {
+            LayerKind::Config => layer.parse(UnsetVariables::Error)?,
+            LayerKind::LocalOverride | LayerKind::Flag => loaded
+                .config
+                .take()
+                .unwrap_or_default()
+                .merge(layer.parse::<ConfigOverride>(UnsetVariables::Error)?),
+        });
+        loaded.sources.push(layer.path);
+    }
+
+    Ok(loaded)
+}
+
+/// Reads the config as committed at `revision`. Variables are substituted
+/// from the current environment, and unset ones are kept as written.
+pub fn load_config_at_revision<P: AsRef<Path>>(
+    git_root: P,
+    revision: &str,
+) -> Result<Option<GarnixConfig>> {
+    let git_root = git_root.as_ref();
+    let mut found = Vec::new();
+    for name in CONFIG_FILE_NAMES {
+        if let Some(contents) = git::read_file_at_revision(git_root, revision, name)? {
+            found.push((name, contents));
+        }
+    }
+
+    let location = format!("at {}", revision);
+    let names = found.iter().map(|(name, _)| *name).collect();
+    let Some(name) = single_config_file(names, &location)? else {
+        return Ok(None);
+    };
+    let (_, contents) = found
+        .into_iter()
+        .find(|(found_name, _)| *found_name == name)
+        .unwrap();
+
+    parse_as(
+        &contents,
+        ConfigFormat::from_path(name),
+        &format!("{}@{}", name, revision),
+        UnsetVariables::Keep,
+    )
+    .map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(dir: &Path, name: &str, contents: &str) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_layered_config_applies_local_then_explicit_overrides() {
+        let dir = tempfile::tempdir().unwrap();
+        write(
+            dir.path(),
+            CONFIG_FILE_NAME,
+            "builds:\n  include: [\"packages.*.*\"]\n",
+        );
+        write(
+            dir.path(),
+            LOCAL_CONFIG_FILE_NAME,
+            "builds:\n  include: [\"checks.*.*\"]\n",
+        );
+        let extra = write(
+            dir.path(),
+            "ci.yaml",
+            "buildsMerge: replace\nbuilds:\n  include: [\"devShells.*.*\"]\n",
+        );
+
+        let local_only = load_layered_config(dir.path(), &[]).unwrap();
+        assert_eq!(local_only.sources.len(), 2);
+        assert_eq!(local_only.config.unwrap().builds.entries().len(), 2);
+
+        let loaded = load_layered_config(dir.path(), &[extra]).unwrap();
+        assert_eq!(loaded.sources.len(), 3);
+        let entries = loaded.config.unwrap().builds.entries().len();
+        assert_eq!(entries, 1);
+    }
+
+    #[test]
+    fn test_layered_config_without_base() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(
+            load_layered_config(dir.path(), &[])
+                .unwrap()
+                .config
+                .is_none()
+        );
+
+        write(
+            dir.path(),
+            LOCAL_CONFIG_FILE_NAME,
+            "builds:\n  include: [\"checks.*.*\"]\n",
+        );
+        let loaded = load_layered_config(dir.path(), &[]).unwrap();
+        // Appended to the default entry, as if garnix.yaml were empty.
+        assert_eq!(loaded.config.unwrap().builds.entries().len(), 2);
+    }
+
+    #[test]
+    fn test_yaml_and_json_parse_identically() {
+        let yaml: GarnixConfig = parse_as(
+            include_str!("../../tests/fixtures/sample_garnix.yaml"),
+            ConfigFormat::Yaml,
+            "sample_garnix.yaml",
+            UnsetVariables::Error,
+        )
+        .unwrap();
+        let json: GarnixConfig = parse_as(
+            include_str!("../../tests/fixtures/sample_garnix.json"),
+            ConfigFormat::Json,
+            "sample_garnix.json",
+            UnsetVariables::Error,
+        )
+        .unwrap();
+
+        assert_eq!(yaml, json);
+        assert_eq!(yaml.builds.entries().len(), 3);
+    }
+
+    #[test]
+    fn test_find_config_file_alternate_names() {
+        for name in CONFIG_FILE_NAMES {
+            let dir = tempfile::tempdir().unwrap();
+            let contents = if name.ends_with(".json") {
+                r#"{"builds": {"include": ["checks.*.*"]}}"#
+            } else {
+                "builds:\n  include: [\"checks.*.*\"]\n"
+            };
+            let path = write(dir.path(), name, contents);
+
+            assert_eq!(find_config_file(dir.path()).unwrap(), Some(path));
+            let config = load_config_from_git_root(dir.path()).unwrap().unwrap();
+            assert_eq!(config.builds.entries()[0].include, vec!["checks.*.*"]);
+        }
+    }
+
+    #[test]
+    fn test_multiple_config_files_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "garnix.yaml", "{}");
+        write(dir.path(), "garnix.json", "{}");
+
+        let error = find_config_file(dir.path()).unwrap_err();
+        assert!(matches!(error, GarnixError::Config(_)));
+        assert!(error.to_string().contains("garnix.yaml, garnix.json"));
+    }
+
+    #[test]
+    fn test_invalid_json_keeps_location() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write(dir.path(), "garnix.json", "{\n  \"builds\": [,\n}");
+
+        match load_config(&path).unwrap_err() {
+            GarnixError::ConfigInvalid { line, .. } => assert_eq!(line, Some(2)),
+            other => panic!("unexpected error: {}", other),
+        }
+    }
+
+    #[test]
+    fn test_environment_variables_are_substituted() {
+        let lookup = |name: &str| (name == "SYSTEM").then(|| "aarch64-linux".to_string());
+        let yaml = "builds:\n  include: [\"packages.${SYSTEM}.*\"]\n  branch: ${BRANCH:-main}\n  timeout: ${TIMEOUT:-30m}\n";
+        let json = r#"{"builds": {"include": ["packages.${SYSTEM}.*"], "branch": "${BRANCH:-main}", "timeout": "${TIMEOUT:-30m}"}}"#;
+
+        let from_yaml: GarnixConfig = parse_with_env(
+            yaml,
+            ConfigFormat::Yaml,
+            "garnix.yaml",
+            UnsetVariables::Error,
+            &lookup,
+        )
+        .unwrap();
+        let from_json: GarnixConfig = parse_with_env(
+            json,
+            ConfigFormat::Json,
+            "garnix.json",
+            UnsetVariables::Error,
+            &lookup,
+        )
+        .unwrap();
+        assert_eq!(from_yaml, from_json);
+
+        let entry = from_yaml.builds.entries()[0].clone();
+        assert_eq!(entry.include, vec!["packages.aarch64-linux.*"]);
+        assert_eq!(entry.branch.as_deref(), Some("main"));
+        assert_eq!(entry.timeout, Some(std::time::Duration::from_secs(30 * 60)));
+
+        let unset = |mode| {
+            parse_with_env::<GarnixConfig>(
+                "builds:\n  branch: ${BRANCH}\n",
+                ConfigFormat::Yaml,
+                "garnix.yaml",
+                mode,
+                &lookup,
+            )
+        };
+        match unset(UnsetVariables::Error) {
+            Err(GarnixError::Config(message)) => assert!(message.contains("builds.branch")),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        let kept = unset(UnsetVariables::Keep).unwrap();
+        assert_eq!(
+            kept.builds.entries()[0].branch.as_deref(),
+            Some("${BRANCH}")
+        );
+    }
+
+    #[test]
+    fn test_dollar_outside_references_keeps_line_numbers() {
+        let yaml = "# costs $5\nbuilds:\n  include: [\"$x.*.*\"]\n  timeout: soon\n";
+        let error = parse_with_env::<GarnixConfig>(
+            yaml,
+            ConfigFormat::Yaml,
+            "garnix.yaml",
+            UnsetVariables::Error,
+            &|_| None,
+        )
+        .unwrap_err();
+        match error {
+            GarnixError::ConfigInvalid { line, .. } => assert!(line.is_some()),
+            other => panic!("unexpected error: {}", other),
+        }
+    }
+
+    #[test]
+    fn test_missing_explicit_override_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let result = load_layered_config(dir.path(), &[dir.path().join("nope.yaml")]);
+        assert!(matches!(result, Err(GarnixError::Config(_))));
+    }
 }
@@ -0,0 +1,74 @@
+use crate::config::types::GarnixConfig;
+use schemars::generate::SchemaSettings;
+
+/// JSON Schema (draft 2020-12) for garnix.yaml, derived from the serde types.
+/// It has no `$id`: the schema isn't published anywhere, editors load it
+/// from wherever `garnix config schema` wrote it.
+pub fn config_schema() -> serde_json::Value {
+    let generator = SchemaSettings::draft2020_12().into_generator();
+    let mut schema = generator.into_root_schema_for::<GarnixConfig>();
+    schema.insert("title".to_string(), "garnix.yaml".into());
+    schema.to_value()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::parse_config;
+
+    fn validate(yaml: &str) -> bool {
+        let schema = config_schema();
+        let validator = jsonschema::validator_for(&schema).unwrap();
+        let instance: serde_json::Value = serde_yaml::from_str(yaml).unwrap();
+        validator.is_valid(&instance)
+    }
+
+    #[test]
+    fn test_fixtures_match_schema() {
+        for fixture in [
+            include_str!("../../tests/fixtures/sample_garnix.yaml"),
+            include_str!("../../tests/fixtures/sample_garnix.json"),
+        ] {
+            assert!(validate(fixture));
+            assert!(parse_config(fixture).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_schema_agrees_with_serde() {
+        let cases = [
+            ("{}", true),
+            ("builds:\n  include: [\"packages.*.*\"]\n", true),
+            ("builds:\n  - timeout: 30m\n  - timeout: 90\n", true),
+            ("incrementalizeBuilds:\n  exclude_branches: [main]\n", true),
+            (
+                "servers:\n  - configuration: web\n    deployment:\n      type: on-branch\n      branch: main\n",
+                true,
+            ),
+            ("builds: 3\n", false),
+            ("builds:\n  include: packages.*.*\n", false),
+            ("builds:\n  - timeout: soon\n", false),
+            ("incrementalizeBuilds: sometimes\n", false),
+            (
+                "servers:\n  - configuration: web\n    deployment:\n      type: on-branch\n",
+                false,
+            ),
+            (
+                "servers:\n  - configuration: web\n    deployment:\n      type: nightly\n",
+                false,
+            ),
+        ];
+
+        for (yaml, valid) in cases {
+            assert_eq!(validate(yaml), valid, "schema: {}", yaml);
+            assert_eq!(parse_config(yaml).is_ok(), valid, "serde: {}", yaml);
+        }
+    }
+
+    #[test]
+    fn test_schema_allows_variable_timeouts() {
+        // Only valid once load_config has substituted the variable.
+        assert!(validate("builds:\n  timeout: ${TIMEOUT:-30m}\n"));
+        assert!(!validate("builds:\n  timeout: ${TIMEOUT}m\n"));
+    }
+}
@@ -1,10 +1,15 @@
-use serde::Deserialize;
+use crate::duration::parse_duration;
+use schemars::{JsonSchema, Schema, SchemaGenerator, json_schema};
+use serde::{Deserialize, Deserializer};
+use std::time::Duration;
 
-#[derive(Debug, Clone, Deserialize, Default)]
+#[derive(Debug, Clone, PartialEq, Deserialize, JsonSchema, Default)]
 pub struct GarnixConfig {
+    /// Which flake attributes to build, as one entry or a list of entries.
     #[serde(default)]
     pub builds: BuildsConfig,
 
+    /// Only build attributes whose derivations changed.
     #[serde(default, rename = "incrementalizeBuilds")]
     pub incrementalize_builds: IncrementalizeBuilds,
 
@@ -12,38 +17,47 @@ pub struct GarnixConfig {
     pub servers: Vec<ServerConfig>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, JsonSchema)]
 #[serde(untagged)]
 pub enum BuildsConfig {
     Single(BuildEntry),
     Multiple(Vec<BuildEntry>),
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, JsonSchema)]
 pub struct BuildEntry {
+    /// Attribute patterns to build, e.g. `packages.*.*`.
     #[serde(default = "default_includes")]
     pub include: Vec<String>,
 
+    /// Attribute patterns removed from this entry's includes.
     #[serde(default)]
     pub exclude: Vec<String>,
 
+    /// Only apply this entry on the given branch.
     pub branch: Option<String>,
+
+    /// Per-attribute build timeout, e.g. `30m` or a number of seconds.
+    #[serde(default, deserialize_with = "deserialize_timeout")]
+    #[schemars(schema_with = "timeout_schema")]
+    pub timeout: Option<Duration>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, JsonSchema)]
 #[serde(untagged)]
 pub enum IncrementalizeBuilds {
     Boolean(bool),
     ExcludesBranches { exclude_branches: Vec<String> },
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, JsonSchema)]
 pub struct ServerConfig {
+    /// Name of the nixosConfigurations entry to deploy.
     pub configuration: String,
     pub deployment: DeploymentConfig,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, JsonSchema)]
 #[serde(tag = "type")]
 pub enum DeploymentConfig {
     #[serde(rename = "on-pull-request")]
@@ -65,6 +79,7 @@ impl Default for BuildEntry {
             include: default_includes(),
             exclude: Vec::new(),
             branch: None,
+            timeout: None,
         }
     }
 }
@@ -86,6 +101,35 @@ fn default_includes() -> Vec<String> {
     ]
 }
 
+fn deserialize_timeout<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum RawTimeout {
+        Seconds(u64),
+        Text(String),
+    }
+
+    match Option::<RawTimeout>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(RawTimeout::Seconds(seconds)) => Ok(Some(Duration::from_secs(seconds))),
+        Some(RawTimeout::Text(text)) => parse_duration(&text)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+    }
+}
+
+fn timeout_schema(_: &mut SchemaGenerator) -> Schema {
+    json_schema!({
+        "description": "Per-attribute build timeout, e.g. `30m` or a number of seconds, or `${VAR:-30m}`.",
+        "type": ["integer", "string", "null"],
+        "minimum": 0,
+        "pattern": "^\\s*(\\d+|(\\d+[smhd])+)\\s*$|^\\$\\{[A-Za-z_][A-Za-z0-9_]*(:-[^}]*)?\\}$"
+    })
+}
+
 impl BuildsConfig {
     pub fn entries(&self) -> Vec<&BuildEntry> {
         match self {
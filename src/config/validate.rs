@@ -0,0 +1,174 @@
+use crate::config::types::GarnixConfig;
+use std::collections::BTreeSet;
+
+/// Flake output categories nix knows about, which patterns may name even if
+/// this flake doesn't define them (yet).
+pub const KNOWN_CATEGORIES: [&str; 19] = [
+    "apps",
+    "checks",
+    "darwinConfigurations",
+    "darwinModules",
+    "defaultApp",
+    "defaultPackage",
+    "devShell",
+    "devShells",
+    "formatter",
+    "homeConfigurations",
+    "homeModules",
+    "hydraJobs",
+    "legacyPackages",
+    "lib",
+    "nixosConfigurations",
+    "nixosModules",
+    "overlays",
+    "packages",
+    "templates",
+];
+
+/// Cross-checks `config` against the flake's attributes: server
+/// configurations that aren't nixosConfigurations, and patterns naming an
+/// output category nobody defines. Returns one message per problem.
+pub fn validate(config: &GarnixConfig, available_attrs: &[String]) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let categories: BTreeSet<&str> = available_attrs
+        .iter()
+        .filter_map(|attr| attr.split('.').next())
+        .chain(KNOWN_CATEGORIES)
+        .collect();
+    let mut reported = BTreeSet::new();
+
+    for entry in config.builds.entries() {
+        for pattern in entry.include.iter().chain(&entry.exclude) {
+            let Some(category) = pattern.split('.').next() else {
+                continue;
+            };
+            if category == "*" || categories.contains(category) || !reported.insert(category) {
+                continue;
+            }
+
+            problems.push(format!(
+                "pattern \"{}\" names unknown output category `{}`{}",
+                pattern,
+                category,
+                suggestion(category, categories.iter().copied())
+            ));
+        }
+    }
+
+    let nixos_configurations: Vec<&str> = available_attrs
+        .iter()
+        .filter_map(|attr| attr.strip_prefix("nixosConfigurations."))
+        .collect();
+    for server in &config.servers {
+        if nixos_configurations.contains(&server.configuration.as_str()) {
+            continue;
+        }
+        problems.push(format!(
+            "server configuration `{}` isn't in the flake's nixosConfigurations{}",
+            server.configuration,
+            suggestion(&server.configuration, nixos_configurations.iter().copied())
+        ));
+    }
+
+    problems
+}
+
+/// ", did you mean `x`?" for the closest candidate, if any is close enough.
+fn suggestion<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> String {
+    let threshold = (name.chars().count() / 3).max(2);
+    candidates
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= threshold)
+        .min()
+        .map(|(_, candidate)| format!(", did you mean `{}`?", candidate))
+        .unwrap_or_default()
+}
+
+/// Levenshtein distance between `a` and `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::parse_config;
+
+    fn available() -> Vec<String> {
+        [
+            "checks.x86_64-linux.fmt",
+            "packages.x86_64-linux.hello",
+            "nixosConfigurations.web",
+            "nixosConfigurations.worker",
+        ]
+        .iter()
+        .map(|attr| attr.to_string())
+        .collect()
+    }
+
+    #[test]
+    fn test_validate() {
+        let cases: [(&str, Vec<&str>); 6] = [
+            (
+                "builds:\n  include: [\"packages.*.*\", \"*.*.fmt\"]\n",
+                vec![],
+            ),
+            (
+                // Known to nix, just not defined by this flake.
+                "builds:\n  include: [\"apps.*.*\"]\n",
+                vec![],
+            ),
+            (
+                "builds:\n  include: [\"package.*.*\"]\n  exclude: [\"package.*.slow\"]\n",
+                vec![
+                    "pattern \"package.*.*\" names unknown output category `package`, did you mean `packages`?",
+                ],
+            ),
+            (
+                "builds:\n  include: [\"frobnicate.*.*\"]\n",
+                vec!["pattern \"frobnicate.*.*\" names unknown output category `frobnicate`"],
+            ),
+            (
+                "servers:\n  - configuration: web\n    deployment:\n      type: on-pull-request\n",
+                vec![],
+            ),
+            (
+                "servers:\n  - configuration: wbe\n    deployment:\n      type: on-pull-request\n",
+                vec![
+                    "server configuration `wbe` isn't in the flake's nixosConfigurations, did you mean `web`?",
+                ],
+            ),
+        ];
+
+        for (yaml, expected) in cases {
+            let config = parse_config(yaml).unwrap();
+            assert_eq!(
+                validate(&config, &available()),
+                expected,
+                "config:\n{}",
+                yaml
+            );
+        }
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("package", "packages"), 1);
+        assert_eq!(edit_distance("chekcs", "checks"), 2);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("same", "same"), 0);
+    }
+}
@@ -0,0 +1,135 @@
+use crate::config::GarnixConfig;
+use crate::error::Result;
+use crate::matcher::AttributeMatcher;
+use std::collections::BTreeSet;
+
+pub struct BranchDiff {
+    pub branch: String,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl BranchDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Branches worth comparing: every branch named by either config, plus the current one.
+pub fn branches_to_compare(
+    current_branch: &str,
+    from: &Option<GarnixConfig>,
+    to: &Option<GarnixConfig>,
+) -> Vec<String> {
+    let mut branches = BTreeSet::new();
+    branches.insert(current_branch.to_string());
+
+    for config in [from, to].into_iter().flatten() {
+        for entry in config.builds.entries() {
+            if let Some(branch) = &entry.branch {
+                branches.insert(branch.clone());
+            }
+        }
+    }
+
+    branches.into_iter().collect()
+}
+
+pub fn diff_build_sets(
+    from: &Option<GarnixConfig>,
+    to: &Option<GarnixConfig>,
+    branches: &[String],
+    available_attrs: &[String],
+) -> Result<Vec<BranchDiff>> {
+    let mut diffs = Vec::new();
+
+    for branch in branches {
+        let matcher = AttributeMatcher::new(branch.clone());
+        let before: BTreeSet<String> = matcher
+            .get_matching_attributes(from, available_attrs)?
+            .into_iter()
+            .collect();
+        let after: BTreeSet<String> = matcher
+            .get_matching_attributes(to, available_attrs)?
+            .into_iter()
+            .collect();
+
+        diffs.push(BranchDiff {
+            branch: branch.clone(),
+            added: after.difference(&before).cloned().collect(),
+            removed: before.difference(&after).cloned().collect(),
+        });
+    }
+
+    Ok(diffs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::parse_config;
+
+    #[test]
+    fn test_diff_build_sets() {
+        let from = Some(
+            parse_config(
+                r#"
+builds:
+  - include: ["packages.*.*"]
+    exclude: ["packages.*.broken"]
+"#,
+            )
+            .unwrap(),
+        );
+        let to = Some(
+            parse_config(
+                r#"
+builds:
+  - include: ["packages.*.*", "checks.*.*"]
+    exclude: []
+  - include: ["devShells.*.*"]
+    branch: main
+"#,
+            )
+            .unwrap(),
+        );
+        let available = vec![
+            "packages.x86_64-linux.hello".to_string(),
+            "packages.x86_64-linux.broken".to_string(),
+            "checks.x86_64-linux.fmt".to_string(),
+            "devShells.x86_64-linux.default".to_string(),
+        ];
+
+        let branches = branches_to_compare("feature", &from, &to);
+        assert_eq!(branches, vec!["feature".to_string(), "main".to_string()]);
+
+        let diffs = diff_build_sets(&from, &to, &branches, &available).unwrap();
+
+        assert_eq!(diffs[0].branch, "feature");
+        assert_eq!(
+            diffs[0].added,
+            vec![
+                "checks.x86_64-linux.fmt".to_string(),
+                "packages.x86_64-linux.broken".to_string()
+            ]
+        );
+        assert!(diffs[0].removed.is_empty());
+
+        assert_eq!(diffs[1].branch, "main");
+        assert!(
+            diffs[1]
+                .added
+                .contains(&"devShells.x86_64-linux.default".to_string())
+        );
+    }
+
+    #[test]
+    fn test_identical_configs_have_empty_diff() {
+        let config = Some(parse_config("builds:\n  include: [\"packages.*.*\"]\n").unwrap());
+        let available = vec!["packages.x86_64-linux.hello".to_string()];
+
+        let diffs = diff_build_sets(&config, &config, &["main".to_string()], &available).unwrap();
+
+        assert!(diffs.iter().all(BranchDiff::is_empty));
+    }
+}
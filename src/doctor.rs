@@ -0,0 +1,158 @@
+use crate::cache::AttributeCache;
+use crate::config;
+use crate::git;
+use crate::nix::{self, NixVersion};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Ok,
+    /// Something optional is missing; garnix still works.
+    Warning,
+    Failed,
+}
+
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: &'static str, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Runs every environment check `garnix run` depends on.
+pub async fn run_checks() -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    match git::get_git_root() {
+        Ok(git_root) => {
+            checks.push(Check::new(
+                "git repository",
+                CheckStatus::Ok,
+                git_root.display().to_string(),
+            ));
+
+            checks.push(if git_root.join("flake.nix").exists() {
+                Check::new("flake.nix", CheckStatus::Ok, "found")
+            } else {
+                Check::new(
+                    "flake.nix",
+                    CheckStatus::Failed,
+                    "no flake.nix at the repository root",
+                )
+            });
+
+            checks.push(match config::find_config_file(&git_root) {
+                // Loaded the way `garnix run` loads it, so a `${VAR}` that
+                // isn't set fails here rather than at build time.
+                Ok(Some(path)) => {
+                    let name = config::CONFIG_FILE_NAMES
+                        .into_iter()
+                        .find(|name| path.ends_with(name))
+                        .unwrap_or("config");
+                    match config::load_layered_config(&git_root, &[]) {
+                        Ok(_) => {
+                            Check::new(name, CheckStatus::Ok, format!("parsed {}", path.display()))
+                        }
+                        Err(e) => Check::new(name, CheckStatus::Failed, e.to_string()),
+                    }
+                }
+                Ok(None) => Check::new(
+                    "config",
+                    CheckStatus::Warning,
+                    "no config file found, defaults will be used",
+                ),
+                Err(e) => Check::new("config", CheckStatus::Failed, e.to_string()),
+            });
+        }
+        Err(e) => checks.push(Check::new(
+            "git repository",
+            CheckStatus::Failed,
+            e.to_string(),
+        )),
+    }
+
+    let nix_bin = nix::nix_bin_from_env();
+    match nix::detect_nix_version(&nix_bin).await {
+        Some(version) => {
+            checks.push(nix_version_check(&nix_bin, version));
+            checks.push(match nix::missing_experimental_features(&nix_bin).await {
+                Ok(missing) if missing.is_empty() => Check::new(
+                    "experimental features",
+                    CheckStatus::Ok,
+                    nix::REQUIRED_EXPERIMENTAL_FEATURES.join(", "),
+                ),
+                // Features enabled through NIX_CONFIG, flags or a nix
+                // distribution's defaults may not be listed, so this only warns.
+                Ok(missing) => Check::new(
+                    "experimental features",
+                    CheckStatus::Warning,
+                    format!(
+                        "{}\n(ignore this if flakes work, they may be enabled another way)",
+                        nix::experimental_features_help(&missing)
+                    ),
+                ),
+                Err(e) => Check::new("experimental features", CheckStatus::Warning, e.to_string()),
+            });
+        }
+        None => checks.push(Check::new(
+            "nix",
+            CheckStatus::Failed,
+            format!(
+                "`{} --version` failed, install nix from https://nixos.org/download or set {}",
+                nix_bin,
+                nix::NIX_BIN_ENV
+            ),
+        )),
+    }
+
+    checks.push(if nix::nom_available().await {
+        Check::new("nom", CheckStatus::Ok, "found, used by --builder auto")
+    } else {
+        Check::new(
+            "nom",
+            CheckStatus::Warning,
+            "not installed, garnix will render build progress itself",
+        )
+    });
+
+    checks.push(if nix::nix_eval_jobs_available().await {
+        Check::new(
+            "nix-eval-jobs",
+            CheckStatus::Ok,
+            "found, used for parallel evaluation",
+        )
+    } else {
+        Check::new(
+            "nix-eval-jobs",
+            CheckStatus::Warning,
+            "not installed, attributes will be evaluated one at a time",
+        )
+    });
+
+    checks.push(match AttributeCache::default_location() {
+        Ok(cache) => Check::new(
+            "attribute cache",
+            CheckStatus::Ok,
+            cache.dir().display().to_string(),
+        ),
+        Err(e) => Check::new("attribute cache", CheckStatus::Warning, e.to_string()),
+    });
+
+    checks
+}
+
+fn nix_version_check(nix_bin: &str, version: NixVersion) -> Check {
+    match nix::check_version(version) {
+        Ok(()) => Check::new("nix", CheckStatus::Ok, format!("{} ({})", version, nix_bin)),
+        Err(e) => Check::new("nix", CheckStatus::Failed, e.to_string()),
+    }
+}
@@ -0,0 +1,116 @@
+use std::time::Duration;
+
+/// Parses durations like `90`, `30s`, `10m`, `2h` or `1h30m`. Bare numbers are seconds.
+pub fn parse_duration(input: &str) -> Result<Duration, String> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Err("empty duration".to_string());
+    }
+
+    let too_large = || format!("invalid duration '{}': too large", input);
+
+    if input.chars().all(|c| c.is_ascii_digit()) {
+        return input
+            .parse::<u64>()
+            .map(Duration::from_secs)
+            .map_err(|_| too_large());
+    }
+
+    let mut total = 0u64;
+    let mut number = String::new();
+
+    for c in input.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+
+        let multiplier = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => {
+                return Err(format!(
+                    "invalid duration '{}': unknown unit '{}'",
+                    input, c
+                ));
+            }
+        };
+        if number.is_empty() {
+            return Err(format!(
+                "invalid duration '{}': missing number before '{}'",
+                input, c
+            ));
+        }
+        total = number
+            .parse::<u64>()
+            .ok()
+            .and_then(|value| value.checked_mul(multiplier))
+            .and_then(|seconds| total.checked_add(seconds))
+            .ok_or_else(too_large)?;
+        number.clear();
+    }
+
+    if !number.is_empty() {
+        return Err(format!(
+            "invalid duration '{}': trailing number without a unit",
+            input
+        ));
+    }
+
+    Ok(Duration::from_secs(total))
+}
+
+/// Formats a duration in the same notation `parse_duration` accepts.
+pub fn format_duration(duration: Duration) -> String {
+    let mut seconds = duration.as_secs();
+    if seconds == 0 {
+        return "0s".to_string();
+    }
+
+    let mut output = String::new();
+    for (unit, size) in [("d", 86400), ("h", 3600), ("m", 60), ("s", 1)] {
+        if seconds >= size {
+            output.push_str(&format!("{}{}", seconds / size, unit));
+            seconds %= size;
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("10m"), Ok(Duration::from_secs(600)));
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(5400)));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("10x").is_err());
+        assert!(parse_duration("1h30").is_err());
+        assert!(parse_duration("m").is_err());
+
+        for overflowing in [
+            "99999999999999999999",
+            "99999999999999999999s",
+            "9999999999999999d",
+            "18446744073709551615s1s",
+        ] {
+            assert_eq!(
+                parse_duration(overflowing),
+                Err(format!("invalid duration '{}': too large", overflowing))
+            );
+        }
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_secs(5400)), "1h30m");
+        assert_eq!(format_duration(Duration::from_secs(45)), "45s");
+        assert_eq!(format_duration(Duration::ZERO), "0s");
+    }
+}
@@ -20,14 +20,146 @@ pub enum GarnixError {
     #[error("No flake.nix found")]
     NoFlakeFound,
 
+    #[error("`{0}` not found, is nix installed and on your PATH?")]
+    NixNotInstalled(String),
+
+    #[error("{0}")]
+    Environment(String),
+
     #[error("Nix command failed: {0}")]
     NixCommand(String),
 
+    #[error("Evaluation failed for: {}", .0.join(", "))]
+    EvalFailed(Vec<String>),
+
+    #[error("Build failed for: {}", .0.join(", "))]
+    BuildFailed(Vec<String>),
+
+    #[error("Build timed out for: {}", .0.join(", "))]
+    Timeout(Vec<String>),
+
+    #[error("Builds cancelled")]
+    Cancelled,
+
     #[error("Pattern matching error: {0}")]
     PatternMatch(String),
 
     #[error("Configuration error: {0}")]
     Config(String),
+
+    #[error("Invalid configuration in {file}{}: {message}", location(*.line, *.column))]
+    ConfigInvalid {
+        file: String,
+        line: Option<usize>,
+        column: Option<usize>,
+        message: String,
+    },
+}
+
+fn location(line: Option<usize>, column: Option<usize>) -> String {
+    match (line, column) {
+        (Some(line), Some(column)) => format!(":{}:{}", line, column),
+        (Some(line), None) => format!(":{}", line),
+        _ => String::new(),
+    }
+}
+
+impl GarnixError {
+    /// Builds a `ConfigInvalid` error from a YAML error, keeping its location.
+    pub fn invalid_config(file: impl Into<String>, error: serde_yaml::Error) -> Self {
+        let location = error.location();
+        GarnixError::ConfigInvalid {
+            file: file.into(),
+            line: location.as_ref().map(|l| l.line()),
+            column: location.as_ref().map(|l| l.column()),
+            message: error.to_string(),
+        }
+    }
+
+    /// Builds a `ConfigInvalid` error from a JSON error, keeping its location.
+    pub fn invalid_json_config(file: impl Into<String>, error: serde_json::Error) -> Self {
+        GarnixError::ConfigInvalid {
+            file: file.into(),
+            line: Some(error.line()),
+            column: Some(error.column()),
+            message: error.to_string(),
+        }
+    }
+
+    /// Process exit code for this error:
+    ///
+    /// | code | meaning                                               |
+    /// |------|-------------------------------------------------------|
+    /// | 1    | unexpected error (IO, git, nix command failures, ...) |
+    /// | 2    | invalid configuration or patterns                     |
+    /// | 3    | environment problem: no nix, no flake, no git repo    |
+    /// | 4    | one or more attributes failed to evaluate             |
+    /// | 5    | one or more attributes failed to build                |
+    /// | 6    | one or more builds timed out                          |
+    /// | 130  | interrupted by Ctrl-C / SIGTERM                       |
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            GarnixError::Git(_)
+            | GarnixError::Json(_)
+            | GarnixError::Io(_)
+            | GarnixError::NixCommand(_) => 1,
+            GarnixError::Yaml(_)
+            | GarnixError::PatternMatch(_)
+            | GarnixError::Config(_)
+            | GarnixError::ConfigInvalid { .. } => 2,
+            GarnixError::NotInGitRepo
+            | GarnixError::NoFlakeFound
+            | GarnixError::NixNotInstalled(_)
+            | GarnixError::Environment(_) => 3,
+            GarnixError::EvalFailed(_) => 4,
+            GarnixError::BuildFailed(_) => 5,
+            GarnixError::Timeout(_) => 6,
+            GarnixError::Cancelled => 130,
+        }
+    }
+
+    /// Whether the command already printed what went wrong, attribute by
+    /// attribute, so only the exit code is left to report.
+    pub fn is_reported(&self) -> bool {
+        matches!(
+            self,
+            GarnixError::EvalFailed(_)
+                | GarnixError::BuildFailed(_)
+                | GarnixError::Timeout(_)
+                | GarnixError::Cancelled
+        )
+    }
 }
 
 pub type Result<T> = std::result::Result<T, GarnixError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalid_config_location() {
+        let yaml_error = serde_yaml::from_str::<Vec<String>>("a: [").unwrap_err();
+        let error = GarnixError::invalid_config("garnix.yaml", yaml_error);
+
+        assert_eq!(error.exit_code(), 2);
+        assert!(
+            error
+                .to_string()
+                .starts_with("Invalid configuration in garnix.yaml:1:")
+        );
+    }
+
+    #[test]
+    fn test_exit_codes_distinguish_config_from_build_failures() {
+        assert_eq!(GarnixError::Config("bad".to_string()).exit_code(), 2);
+        assert_eq!(
+            GarnixError::BuildFailed(vec!["checks.x86_64-linux.fmt".to_string()]).exit_code(),
+            5
+        );
+        assert_eq!(
+            GarnixError::NixNotInstalled("nix".to_string()).exit_code(),
+            3
+        );
+    }
+}
@@ -1,6 +1,6 @@
 use crate::error::{GarnixError, Result};
-use git2::Repository;
-use std::path::PathBuf;
+use git2::{BranchType, ObjectType, Oid, Repository};
+use std::path::{Path, PathBuf};
 
 pub fn get_current_branch() -> Result<String> {
     let repo = Repository::discover(std::env::current_dir()?)?;
@@ -68,3 +68,89 @@ pub fn get_branch_or_override(override_branch: Option<String>) -> Result<String>
         }
     }
 }
+
+/// Local branches plus remote branches without their remote prefix, sorted
+/// and deduplicated.
+pub fn list_branches<P: AsRef<Path>>(git_root: P) -> Result<Vec<String>> {
+    let repo = Repository::open(git_root)?;
+    let mut names = Vec::new();
+
+    for branch in repo.branches(None)? {
+        let (branch, kind) = branch?;
+        let Some(name) = branch.name()? else {
+            continue;
+        };
+        match kind {
+            BranchType::Local => names.push(name.to_string()),
+            BranchType::Remote => match name.split_once('/') {
+                Some((_, name)) if name != "HEAD" => names.push(name.to_string()),
+                _ => {}
+            },
+        }
+    }
+
+    names.sort();
+    names.dedup();
+    Ok(names)
+}
+
+pub fn read_file_at_revision<P: AsRef<Path>>(
+    git_root: P,
+    revision: &str,
+    file_path: &str,
+) -> Result<Option<String>> {
+    let repo = Repository::open(git_root)?;
+    let tree = repo.revparse_single(revision)?.peel_to_tree()?;
+
+    let entry = match tree.get_path(Path::new(file_path)) {
+        Ok(entry) => entry,
+        Err(e) if e.code() == git2::ErrorCode::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    let blob = entry.to_object(&repo)?.peel_to_blob()?;
+    let contents = String::from_utf8(blob.content().to_vec()).map_err(|_| {
+        GarnixError::Config(format!(
+            "{} at revision {} is not valid UTF-8",
+            file_path, revision
+        ))
+    })?;
+
+    Ok(Some(contents))
+}
+
+/// Hashes the HEAD tree together with any uncommitted changes to tracked files,
+/// i.e. the source nix sees when evaluating the flake from this checkout.
+/// Changed files are hashed by content, so binary files count too.
+pub fn working_tree_hash<P: AsRef<Path>>(git_root: P) -> Result<String> {
+    let git_root = git_root.as_ref();
+    let repo = Repository::open(git_root)?;
+    let head_tree = repo.head().ok().and_then(|head| head.peel_to_tree().ok());
+
+    let mut state = Vec::new();
+    if let Some(tree) = &head_tree {
+        state.extend_from_slice(tree.id().as_bytes());
+    }
+
+    let diff = repo.diff_tree_to_workdir_with_index(head_tree.as_ref(), None)?;
+    for delta in diff.deltas() {
+        let file = delta.new_file();
+        state.push(delta.status() as u8);
+        if let Some(path) = file.path() {
+            state.extend_from_slice(path.to_string_lossy().as_bytes());
+        }
+        state.extend_from_slice(&u32::from(file.mode()).to_le_bytes());
+
+        // libgit2 leaves the id of modified workdir files unset unless it
+        // had to hash them, and deleted files have no content to hash.
+        let id = match file.path().map(|path| git_root.join(path)) {
+            Some(path) if file.id().is_zero() && path.is_file() => {
+                Oid::hash_file(ObjectType::Blob, path)?
+            }
+            _ => file.id(),
+        };
+        state.extend_from_slice(id.as_bytes());
+    }
+
+    Ok(Oid::hash_object(ObjectType::Blob, &state)?.to_string())
+}
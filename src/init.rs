@@ -0,0 +1,212 @@
+use std::collections::BTreeMap;
+
+/// How many attribute names to list in a group's comment before eliding.
+const MAX_LISTED_NAMES: usize = 5;
+
+/// A set of discovered attributes that share one include pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputGroup {
+    pub category: String,
+    pub system: Option<String>,
+    pub names: Vec<String>,
+}
+
+impl OutputGroup {
+    pub fn pattern(&self) -> String {
+        match &self.system {
+            Some(system) if self.names.is_empty() => format!("{}.{}", self.category, system),
+            Some(system) => format!("{}.{}.*", self.category, system),
+            None => format!("{}.*", self.category),
+        }
+    }
+
+    fn describe(&self) -> String {
+        let label = match &self.system {
+            Some(system) => format!("{} ({})", self.category, system),
+            None => self.category.clone(),
+        };
+        if self.names.is_empty() {
+            return label;
+        }
+
+        let mut names = self.names[..self.names.len().min(MAX_LISTED_NAMES)].join(", ");
+        if self.names.len() > MAX_LISTED_NAMES {
+            names.push_str(&format!(
+                " and {} more",
+                self.names.len() - MAX_LISTED_NAMES
+            ));
+        }
+        format!("{}: {}", label, names)
+    }
+}
+
+fn looks_like_system(part: &str) -> bool {
+    part.ends_with("-linux") || part.ends_with("-darwin")
+}
+
+/// Groups attributes by output category and system, e.g. every
+/// `packages.x86_64-linux.*` attribute ends up in one group.
+pub fn group_outputs(attributes: &[String]) -> Vec<OutputGroup> {
+    let mut groups: BTreeMap<(String, Option<String>), Vec<String>> = BTreeMap::new();
+
+    for attribute in attributes {
+        let parts: Vec<&str> = attribute.splitn(3, '.').collect();
+        let (key, name) = match parts.as_slice() {
+            [category, system, name] => (
+                (category.to_string(), Some(system.to_string())),
+                Some(name.to_string()),
+            ),
+            [category, system] if looks_like_system(system) => {
+                ((category.to_string(), Some(system.to_string())), None)
+            }
+            [category, name] => ((category.to_string(), None), Some(name.to_string())),
+            _ => continue,
+        };
+
+        let names = groups.entry(key).or_default();
+        names.extend(name);
+    }
+
+    groups
+        .into_iter()
+        .map(|((category, system), mut names)| {
+            names.sort();
+            OutputGroup {
+                category,
+                system,
+                names,
+            }
+        })
+        .collect()
+}
+
+/// Renders a commented garnix.yaml that builds every discovered attribute.
+pub fn scaffold_config(attributes: &[String]) -> String {
+    let groups = group_outputs(attributes);
+    let mut output = String::new();
+
+    output.push_str("# garnix.yaml, generated by `garnix init`\n");
+    output.push_str("# docs: https://garnix.io/docs/yaml_config\n");
+    output.push_str("builds:\n");
+    if groups.is_empty() {
+        output.push_str("  # no flake outputs found, add patterns like \"packages.*.*\"\n");
+        output.push_str("  - include: []\n");
+    } else {
+        output.push_str("  - include:\n");
+    }
+    for group in &groups {
+        output.push_str(&format!("      # {}\n", group.describe()));
+        output.push_str(&format!("      - \"{}\"\n", group.pattern()));
+    }
+
+    let example_exclude = groups
+        .iter()
+        .find(|group| group.system.is_some() && !group.names.is_empty())
+        .map(|group| {
+            format!(
+                "{}.{}",
+                group.pattern().trim_end_matches(".*"),
+                group.names[0]
+            )
+        })
+        .unwrap_or_else(|| "packages.x86_64-linux.slow".to_string());
+    output.push_str(&format!(
+        "    # attributes to skip, e.g. \"{}\"\n",
+        example_exclude
+    ));
+    output.push_str("    exclude: []\n");
+
+    let servers: Vec<&String> = groups
+        .iter()
+        .filter(|group| group.category == "nixosConfigurations")
+        .flat_map(|group| &group.names)
+        .collect();
+    if !servers.is_empty() {
+        output.push('\n');
+        output
+            .push_str("# garnix deploys every server listed here, uncomment the ones you want.\n");
+        output.push_str("# servers:\n");
+        for server in servers {
+            output.push_str(&format!("#   - configuration: {}\n", server));
+            output.push_str("#     deployment:\n");
+            output.push_str("#       type: on-branch\n");
+            output.push_str("#       branch: main\n");
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::fmt::{BuildsStyle, format_config};
+    use crate::config::parse_config;
+    use crate::matcher::AttributeMatcher;
+
+    fn attributes() -> Vec<String> {
+        [
+            "packages.x86_64-linux.hello",
+            "packages.x86_64-linux.broken",
+            "checks.x86_64-linux.fmt",
+            "defaultPackage.x86_64-linux",
+            "nixosConfigurations.web",
+            "nixosConfigurations.db",
+        ]
+        .iter()
+        .map(|attr| attr.to_string())
+        .collect()
+    }
+
+    #[test]
+    fn test_group_outputs() {
+        let patterns: Vec<String> = group_outputs(&attributes())
+            .iter()
+            .map(OutputGroup::pattern)
+            .collect();
+
+        assert_eq!(
+            patterns,
+            vec![
+                "checks.x86_64-linux.*",
+                "defaultPackage.x86_64-linux",
+                "nixosConfigurations.*",
+                "packages.x86_64-linux.*",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scaffold_builds_every_attribute() {
+        let attributes = attributes();
+        let scaffold = scaffold_config(&attributes);
+        let config = parse_config(&scaffold).unwrap();
+
+        let matcher = AttributeMatcher::new("main".to_string());
+        let mut matched = matcher
+            .get_matching_attributes(&Some(config.clone()), &attributes)
+            .unwrap();
+        matched.sort();
+        let mut expected = attributes.clone();
+        expected.sort();
+        assert_eq!(matched, expected);
+
+        assert!(config.servers.is_empty());
+        assert!(scaffold.contains("# packages (x86_64-linux): broken, hello\n"));
+        assert!(scaffold.contains("#   - configuration: db\n"));
+        assert!(scaffold.contains("#   - configuration: web\n"));
+    }
+
+    #[test]
+    fn test_scaffold_is_formatted() {
+        let scaffold = scaffold_config(&attributes());
+        let formatted = format_config(&scaffold, "garnix.yaml", BuildsStyle::List).unwrap();
+        assert_eq!(formatted, scaffold);
+    }
+
+    #[test]
+    fn test_scaffold_without_outputs() {
+        let config = parse_config(&scaffold_config(&[])).unwrap();
+        assert!(config.builds.entries()[0].include.is_empty());
+    }
+}
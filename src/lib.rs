@@ -1,8 +1,17 @@
+pub mod cache;
 pub mod cli;
 pub mod config;
+pub mod diff;
+pub mod doctor;
+pub mod duration;
 pub mod error;
 pub mod git;
+pub mod init;
+pub mod list;
+pub mod logs;
 pub mod matcher;
 pub mod nix;
+pub mod progress;
+pub mod signal;
 
 pub use error::{GarnixError, Result};
@@ -0,0 +1,135 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListFilter {
+    All,
+    Matched,
+    Unmatched,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ListedAttribute {
+    pub name: String,
+    pub matched: bool,
+}
+
+pub fn select_attributes(
+    available_attrs: &[String],
+    matched_attrs: &[String],
+    filter: ListFilter,
+    category: Option<&str>,
+) -> Vec<ListedAttribute> {
+    let matched: HashSet<&str> = matched_attrs.iter().map(String::as_str).collect();
+
+    let mut selected: Vec<ListedAttribute> = available_attrs
+        .iter()
+        .filter(|attr| match category {
+            Some(category) => attr.split('.').next() == Some(category),
+            None => true,
+        })
+        .map(|attr| ListedAttribute {
+            name: attr.clone(),
+            matched: matched.contains(attr.as_str()),
+        })
+        .filter(|attr| match filter {
+            ListFilter::All => true,
+            ListFilter::Matched => attr.matched,
+            ListFilter::Unmatched => !attr.matched,
+        })
+        .collect();
+
+    selected.sort_by(|a, b| a.name.cmp(&b.name));
+    selected
+}
+
+#[derive(Default)]
+struct TreeNode {
+    children: BTreeMap<String, TreeNode>,
+    matched: Option<bool>,
+}
+
+/// Renders attributes as an indented tree, one level per attribute path segment.
+pub fn render_tree(attrs: &[ListedAttribute], mark_matched: bool) -> String {
+    let mut root = TreeNode::default();
+
+    for attr in attrs {
+        let mut node = &mut root;
+        for part in attr.name.split('.') {
+            node = node.children.entry(part.to_string()).or_default();
+        }
+        node.matched = Some(attr.matched);
+    }
+
+    let mut output = String::new();
+    render_node(&root, 0, mark_matched, &mut output);
+    output
+}
+
+fn render_node(node: &TreeNode, depth: usize, mark_matched: bool, output: &mut String) {
+    for (name, child) in &node.children {
+        output.push_str(&"  ".repeat(depth));
+        output.push_str(name);
+        if mark_matched && child.matched == Some(true) {
+            output.push_str(" (matched)");
+        }
+        output.push('\n');
+        render_node(child, depth + 1, mark_matched, output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn available() -> Vec<String> {
+        vec![
+            "packages.x86_64-linux.hello".to_string(),
+            "packages.x86_64-linux.world".to_string(),
+            "checks.x86_64-linux.fmt".to_string(),
+            "devShells.x86_64-linux.default".to_string(),
+        ]
+    }
+
+    #[test]
+    fn test_select_attributes() {
+        let matched = vec![
+            "packages.x86_64-linux.hello".to_string(),
+            "checks.x86_64-linux.fmt".to_string(),
+        ];
+
+        let all = select_attributes(&available(), &matched, ListFilter::All, None);
+        assert_eq!(all.len(), 4);
+        assert_eq!(all[0].name, "checks.x86_64-linux.fmt");
+
+        let unmatched = select_attributes(&available(), &matched, ListFilter::Unmatched, None);
+        let names: Vec<&str> = unmatched.iter().map(|a| a.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "devShells.x86_64-linux.default",
+                "packages.x86_64-linux.world"
+            ]
+        );
+
+        let matched_packages = select_attributes(
+            &available(),
+            &matched,
+            ListFilter::Matched,
+            Some("packages"),
+        );
+        assert_eq!(matched_packages.len(), 1);
+        assert_eq!(matched_packages[0].name, "packages.x86_64-linux.hello");
+    }
+
+    #[test]
+    fn test_render_tree() {
+        let matched = vec!["packages.x86_64-linux.hello".to_string()];
+        let attrs = select_attributes(&available(), &matched, ListFilter::All, Some("packages"));
+
+        assert_eq!(
+            render_tree(&attrs, true),
+            "packages\n  x86_64-linux\n    hello (matched)\n    world\n"
+        );
+    }
+}
@@ -0,0 +1,78 @@
+use crate::error::Result;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Per-attribute build logs under `.garnix/logs` in the repository.
+#[derive(Debug, Clone)]
+pub struct LogStore {
+    dir: PathBuf,
+}
+
+impl LogStore {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn from_git_root<P: AsRef<Path>>(git_root: P) -> Self {
+        Self::new(git_root.as_ref().join(".garnix").join("logs"))
+    }
+
+    pub fn path_for(&self, attribute: &str) -> PathBuf {
+        self.dir.join(format!("{}.log", attribute))
+    }
+
+    /// Creates (or truncates) the log file for an attribute.
+    pub fn create(&self, attribute: &str) -> Result<File> {
+        std::fs::create_dir_all(&self.dir)?;
+
+        // Keep logs out of `git status` without touching the user's .gitignore.
+        if let Some(garnix_dir) = self.dir.parent() {
+            let ignore_file = garnix_dir.join(".gitignore");
+            if garnix_dir.file_name() == Some(".garnix".as_ref()) && !ignore_file.exists() {
+                std::fs::write(ignore_file, "*\n")?;
+            }
+        }
+
+        Ok(File::create(self.path_for(attribute))?)
+    }
+
+    /// Replaces an attribute's log with `contents`, returning the log path.
+    pub fn write(&self, attribute: &str, contents: &str) -> Result<PathBuf> {
+        let mut file = self.create(attribute)?;
+        writeln!(file, "{}", contents)?;
+        Ok(self.path_for(attribute))
+    }
+}
+
+/// Returns the last `count` lines of a log file.
+pub fn tail<P: AsRef<Path>>(path: P, count: usize) -> Result<Vec<String>> {
+    let contents = std::fs::read_to_string(path)?;
+    let lines: Vec<&str> = contents.lines().collect();
+    let start = lines.len().saturating_sub(count);
+    Ok(lines[start..].iter().map(|line| line.to_string()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_and_tail() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LogStore::from_git_root(dir.path());
+
+        let mut file = store.create("checks.x86_64-linux.fmt").unwrap();
+        for i in 1..=5 {
+            writeln!(file, "line {}", i).unwrap();
+        }
+
+        let path = store.path_for("checks.x86_64-linux.fmt");
+        assert!(path.ends_with(".garnix/logs/checks.x86_64-linux.fmt.log"));
+        assert!(dir.path().join(".garnix/.gitignore").exists());
+        assert_eq!(tail(&path, 2).unwrap(), vec!["line 4", "line 5"]);
+        assert_eq!(tail(&path, 10).unwrap().len(), 5);
+    }
+}
@@ -1,43 +1,198 @@
 use clap::Parser;
 use garnix_cli::{
-    Result,
-    cli::{self, Cli, Commands},
-    config, git,
+    GarnixError, Result,
+    cache::AttributeCache,
+    cli::{self, CacheCommands, Cli, Commands, ConfigCommands, OutputFormat, RunArgs},
+    config::{
+        self,
+        effective::{Source, Sourced},
+        fmt::BuildsStyle,
+        lint::Rule,
+        locate::LineMap,
+    },
+    diff,
+    doctor::{self, CheckStatus},
+    duration, git, init,
+    list::{self, ListFilter},
+    logs::{self, LogStore},
     matcher::AttributeMatcher,
-    nix::NixFlake,
+    nix::{BuildOutcome, BuildResult, CacheStatus, DryRunPlan, Evaluation, NixFlake},
+    progress::BuildStats,
+    signal::{self, CancelToken},
 };
+use std::path::PathBuf;
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() {
     let cli = Cli::parse();
 
+    if let Err(e) = run(cli).await {
+        if !e.is_reported() {
+            cli::print_error(&e.to_string());
+        }
+        std::process::exit(e.exit_code());
+    }
+}
+
+async fn run(cli: Cli) -> Result<()> {
     match cli.command {
-        Commands::Run { as_branch, dry_run } => {
-            run_build(as_branch, dry_run).await?;
+        Commands::Run(args) => {
+            run_build(args, &cli.config).await?;
+        }
+        Commands::Diff { from, to, no_cache } => {
+            run_diff(from, to, no_cache).await?;
+        }
+        Commands::List {
+            as_branch,
+            all: _,
+            matched,
+            unmatched,
+            category,
+            format,
+            no_cache,
+        } => {
+            let filter = if matched {
+                ListFilter::Matched
+            } else if unmatched {
+                ListFilter::Unmatched
+            } else {
+                ListFilter::All
+            };
+            run_list(&cli.config, as_branch, filter, category, format, no_cache).await?;
+        }
+        Commands::Init {
+            force,
+            dry_run,
+            no_cache,
+        } => {
+            run_init(force, dry_run, no_cache).await?;
+        }
+        Commands::Doctor => {
+            run_doctor().await?;
+        }
+        Commands::Cache {
+            command: CacheCommands::Clear,
+        } => {
+            let cache = AttributeCache::default_location()?;
+            let removed = cache.clear()?;
+            cli::print_success(&format!(
+                "removed {} cached entries from {}",
+                removed,
+                cache.dir().display()
+            ));
+        }
+        Commands::Config {
+            command: ConfigCommands::Schema,
+        } => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&config::schema::config_schema())?
+            );
+        }
+        Commands::Config {
+            command: ConfigCommands::Show { effective, branch },
+        } => {
+            run_config_show(&cli.config, effective, branch)?;
+        }
+        Commands::Config {
+            command: ConfigCommands::Lint { allow, path },
+        } => {
+            run_config_lint(path, &allow)?;
+        }
+        Commands::Config {
+            command:
+                ConfigCommands::Fmt {
+                    check,
+                    builds,
+                    path,
+                },
+        } => {
+            run_config_fmt(path, builds, check)?;
         }
     }
 
     Ok(())
 }
 
-async fn run_build(as_branch: Option<String>, dry_run: bool) -> Result<()> {
-    let current_branch = git::get_branch_or_override(as_branch)?;
+async fn discover_attributes(flake: &NixFlake, no_cache: bool) -> Result<Vec<String>> {
+    flake.preflight().await?;
+
+    if no_cache {
+        return flake.discover_attributes().await;
+    }
+
+    match AttributeCache::default_location() {
+        Ok(cache) => flake.discover_attributes_cached(&cache).await,
+        Err(_) => flake.discover_attributes().await,
+    }
+}
+
+async fn run_build(args: RunArgs, overrides: &[PathBuf]) -> Result<()> {
+    let current_branch = git::get_branch_or_override(args.as_branch)?;
     cli::print_success(&format!("running builds for branch: {}", current_branch));
 
     let git_root = git::get_git_root()?;
-    let config = config::load_config_from_git_root(&git_root)?;
+    let loaded = config::load_layered_config(&git_root, overrides)?;
 
-    cli::print_success(if config.is_some() {
-        "config loaded from garnix.yaml"
+    if loaded.sources.is_empty() {
+        cli::print_success("no garnix config found, using defaults");
     } else {
-        "no garnix config found, using defaults"
-    });
+        let sources: Vec<String> = loaded
+            .sources
+            .iter()
+            .map(|path| {
+                path.strip_prefix(&git_root)
+                    .unwrap_or(path)
+                    .display()
+                    .to_string()
+            })
+            .collect();
+        cli::print_success(&format!("config loaded from {}", sources.join(", ")));
+    }
+    let config = loaded.config;
 
-    let flake = NixFlake::from_git_root(&git_root)?;
-    let available_attrs = flake.discover_attributes().await?;
+    let flake = NixFlake::from_git_root(&git_root)?
+        .with_builder(args.builder)
+        .with_build_jobs(args.jobs)
+        .with_eval_backend(args.eval_backend, args.eval_workers);
+    if let Some(version) = flake.version().await {
+        cli::print_info(&format!("using {} ({})", version, flake.nix_bin));
+    }
+    let available_attrs = discover_attributes(&flake, args.no_cache).await?;
     let matcher = AttributeMatcher::new(current_branch);
+    let problems = config
+        .as_ref()
+        .map(|config| config::validate::validate(config, &available_attrs))
+        .unwrap_or_default();
+    for problem in &problems {
+        cli::print_warning(problem);
+    }
+    if args.strict && !problems.is_empty() {
+        return Err(GarnixError::Config(
+            "garnix config doesn't match the flake, failing because of --strict".to_string(),
+        ));
+    }
+
     let matching_attrs = matcher.get_matching_attributes(&config, &available_attrs)?;
 
+    if !args.no_exclude_warning {
+        let no_longer_excluded =
+            matcher.get_attributes_no_longer_excluded(&config, &available_attrs)?;
+        if !no_longer_excluded.is_empty() {
+            cli::print_warning(
+                "excludes now only apply to their own build entry, so these are built \
+                 although another entry excludes them:",
+            );
+            for attr in &no_longer_excluded {
+                cli::print_warning(&format!("    {}", attr));
+            }
+            cli::print_warning(
+                "add the excludes to the entries that include these to keep skipping them, \
+                 or pass --no-exclude-warning",
+            );
+        }
+    }
+
     if matching_attrs.is_empty() {
         cli::print_warning("no attributes match the current config");
         println!();
@@ -48,6 +203,23 @@ async fn run_build(as_branch: Option<String>, dry_run: bool) -> Result<()> {
         return Ok(());
     }
 
+    let matching_attrs = if args.only.is_empty() && args.skip.is_empty() {
+        matching_attrs
+    } else {
+        let filtered_attrs = matcher.filter_attributes(&matching_attrs, &args.only, &args.skip)?;
+        cli::print_info(&format!(
+            "--only/--skip kept {}/{} matched attributes",
+            filtered_attrs.len(),
+            matching_attrs.len()
+        ));
+
+        if filtered_attrs.is_empty() {
+            cli::print_warning("no matched attributes left after --only/--skip");
+            return Ok(());
+        }
+        filtered_attrs
+    };
+
     cli::print_success(&format!(
         "matched {}/{} attributes for building:",
         matching_attrs.len(),
@@ -57,17 +229,479 @@ async fn run_build(as_branch: Option<String>, dry_run: bool) -> Result<()> {
         cli::print_build_target(attr);
     }
 
-    match flake.build_attributes(&matching_attrs, dry_run).await {
-        Ok(()) => {
-            println!();
-            cli::print_success("all builds completed");
+    let log_store = LogStore::from_git_root(&git_root);
+    let mut eval_failures = Vec::new();
+    let mut evaluated_attrs = Vec::new();
+    for evaluated in flake.evaluate_attributes(&matching_attrs).await? {
+        match evaluated.evaluation {
+            Evaluation::Evaluated { .. } => evaluated_attrs.push(evaluated.attribute),
+            Evaluation::Failed { error } => {
+                cli::print_error(&format!("evaluation failed: {}", evaluated.attribute));
+                eval_failures.push(BuildResult {
+                    log_path: log_store.write(&evaluated.attribute, &error)?,
+                    attribute: evaluated.attribute,
+                    outcome: BuildOutcome::EvalFailed,
+                    stats: None,
+                });
+            }
+        }
+    }
+    let matching_attrs = evaluated_attrs;
+
+    let mut plan = None;
+    let matching_attrs = if args.dry_run || args.skip_cached {
+        let statuses = flake.classify_attributes(&matching_attrs).await?;
+
+        println!();
+        cli::print_info("store status:");
+        for status in &statuses {
+            cli::print_attribute_status(&status.attribute, status.status);
         }
-        Err(e) => {
+        // Cached attributes have empty plans, so skipping them changes nothing.
+        plan = Some(DryRunPlan::combine(
+            statuses.iter().map(|status| &status.plan),
+        ));
+
+        if args.skip_cached {
+            let to_build: Vec<String> = statuses
+                .into_iter()
+                .filter(|status| status.status != CacheStatus::Cached)
+                .map(|status| status.attribute)
+                .collect();
+
+            if to_build.is_empty() && eval_failures.is_empty() {
+                println!();
+                cli::print_success("all matched attributes are already in the local store");
+                return Ok(());
+            }
+            to_build
+        } else {
+            matching_attrs
+        }
+    } else {
+        matching_attrs
+    };
+
+    if args.dry_run {
+        if let Some(plan) = plan.filter(|_| !matching_attrs.is_empty()) {
             println!();
-            cli::print_error(&format!("build failed: {}", e));
-            std::process::exit(1);
+            cli::print_dry_run_plan(&plan);
         }
+        if eval_failures.is_empty() {
+            return Ok(());
+        }
+        println!();
+        cli::print_error(&format!(
+            "{} attributes failed to evaluate and would not be built",
+            eval_failures.len()
+        ));
+        return Err(GarnixError::EvalFailed(
+            eval_failures
+                .into_iter()
+                .map(|failure| failure.attribute)
+                .collect(),
+        ));
+    }
+
+    let mut timeouts = matcher.get_attribute_timeouts(&config, &matching_attrs)?;
+    if let Some(timeout) = args.timeout {
+        for attr in &matching_attrs {
+            timeouts.insert(attr.clone(), timeout);
+        }
+    }
+
+    let cancel = CancelToken::new();
+    signal::cancel_on_signal(cancel.clone())?;
+    let mut results = flake
+        .build_attributes(&matching_attrs, &log_store, &timeouts, &cancel)
+        .await?;
+    results.extend(eval_failures);
+    results.sort_by(|a, b| a.attribute.cmp(&b.attribute));
+
+    let count = |outcome| {
+        results
+            .iter()
+            .filter(|result| result.outcome == outcome)
+            .count()
+    };
+    let eval_failed = count(BuildOutcome::EvalFailed);
+    let failed = count(BuildOutcome::Failed);
+    let timed_out = count(BuildOutcome::TimedOut);
+    let cancelled = count(BuildOutcome::Cancelled);
+
+    println!();
+    let mut stats = None;
+    for result_stats in results.iter().filter_map(|result| result.stats.as_ref()) {
+        stats
+            .get_or_insert_with(BuildStats::default)
+            .add(result_stats);
+    }
+    if let Some(stats) = stats {
+        cli::print_info(&format!(
+            "built {} derivations, fetched {} paths",
+            stats.derivations_completed.len(),
+            stats.paths_fetched
+        ));
+    }
+    if eval_failed == 0 && failed == 0 && timed_out == 0 && cancelled == 0 {
+        cli::print_success("all builds completed");
+        return Ok(());
+    }
+
+    for result in &results {
+        match result.outcome {
+            BuildOutcome::EvalFailed | BuildOutcome::Failed | BuildOutcome::TimedOut => {
+                cli::print_error(&match result.outcome {
+                    BuildOutcome::EvalFailed => {
+                        format!("evaluation failed: {}", result.attribute)
+                    }
+                    BuildOutcome::TimedOut => format!(
+                        "build timed out after {}: {}",
+                        duration::format_duration(timeouts[&result.attribute]),
+                        result.attribute
+                    ),
+                    _ => format!("build failed: {}", result.attribute),
+                });
+                cli::print_info(&format!("log: {}", result.log_path.display()));
+                if let Ok(lines) = logs::tail(&result.log_path, args.log_tail) {
+                    for line in lines {
+                        println!("    {}", line);
+                    }
+                }
+                println!();
+            }
+            BuildOutcome::Cancelled => {
+                cli::print_warning(&format!("cancelled: {}", result.attribute));
+            }
+            BuildOutcome::Succeeded => {}
+        }
+    }
+
+    cli::print_error(&format!(
+        "{} succeeded, {} failed to evaluate, {} failed to build, {} timed out, {} cancelled",
+        count(BuildOutcome::Succeeded),
+        eval_failed,
+        failed,
+        timed_out,
+        cancelled
+    ));
+
+    let attributes_with = |outcome| {
+        results
+            .iter()
+            .filter(|result| result.outcome == outcome)
+            .map(|result| result.attribute.clone())
+            .collect()
+    };
+    Err(if cancelled > 0 {
+        GarnixError::Cancelled
+    } else if failed > 0 {
+        GarnixError::BuildFailed(attributes_with(BuildOutcome::Failed))
+    } else if timed_out > 0 {
+        GarnixError::Timeout(attributes_with(BuildOutcome::TimedOut))
+    } else {
+        GarnixError::EvalFailed(attributes_with(BuildOutcome::EvalFailed))
+    })
+}
+
+async fn run_diff(from: String, to: Option<String>, no_cache: bool) -> Result<()> {
+    let git_root = git::get_git_root()?;
+    let current_branch = git::get_current_branch()?;
+
+    let from_config = config::load_config_at_revision(&git_root, &from)?;
+    let to_config = match &to {
+        Some(rev) => config::load_config_at_revision(&git_root, rev)?,
+        None => config::load_config_from_git_root(&git_root)?,
+    };
+    let to_label = to.as_deref().unwrap_or("working tree");
+
+    cli::print_success(&format!("comparing build sets: {} -> {}", from, to_label));
+
+    let flake = NixFlake::from_git_root(&git_root)?;
+    let available_attrs = discover_attributes(&flake, no_cache).await?;
+    let branches = diff::branches_to_compare(&current_branch, &from_config, &to_config);
+    let diffs = diff::diff_build_sets(&from_config, &to_config, &branches, &available_attrs)?;
+
+    if diffs.iter().all(diff::BranchDiff::is_empty) {
+        cli::print_info("build set unchanged for all branches");
+        return Ok(());
+    }
+
+    for branch_diff in &diffs {
+        println!();
+        if branch_diff.is_empty() {
+            cli::print_info(&format!("branch {}: unchanged", branch_diff.branch));
+            continue;
+        }
+
+        cli::print_info(&format!(
+            "branch {}: {} added, {} removed",
+            branch_diff.branch,
+            branch_diff.added.len(),
+            branch_diff.removed.len()
+        ));
+        for attr in &branch_diff.added {
+            cli::print_added(attr);
+        }
+        for attr in &branch_diff.removed {
+            cli::print_removed(attr);
+        }
+    }
+
+    Ok(())
+}
+
+async fn run_list(
+    overrides: &[PathBuf],
+    as_branch: Option<String>,
+    filter: ListFilter,
+    category: Option<String>,
+    format: OutputFormat,
+    no_cache: bool,
+) -> Result<()> {
+    let current_branch = git::get_branch_or_override(as_branch)?;
+    let git_root = git::get_git_root()?;
+    let config = config::load_layered_config(&git_root, overrides)?.config;
+
+    let flake = NixFlake::from_git_root(&git_root)?;
+    let available_attrs = discover_attributes(&flake, no_cache).await?;
+    let matcher = AttributeMatcher::new(current_branch);
+    let matching_attrs = matcher.get_matching_attributes(&config, &available_attrs)?;
+
+    let attrs = list::select_attributes(
+        &available_attrs,
+        &matching_attrs,
+        filter,
+        category.as_deref(),
+    );
+
+    match format {
+        OutputFormat::Plain => {
+            for attr in &attrs {
+                println!("{}", attr.name);
+            }
+        }
+        OutputFormat::Tree => {
+            print!("{}", list::render_tree(&attrs, filter == ListFilter::All));
+        }
+        OutputFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(&attrs)?);
+        }
+    }
+
+    Ok(())
+}
+
+async fn run_init(force: bool, dry_run: bool, no_cache: bool) -> Result<()> {
+    let git_root = git::get_git_root()?;
+    let existing = config::find_config_file(&git_root)?;
+    let target = match &existing {
+        Some(path) if config::ConfigFormat::from_path(path) == config::ConfigFormat::Json => {
+            return Err(GarnixError::Config(format!(
+                "{} already exists and `garnix init` only writes YAML, remove it first",
+                path.display()
+            )));
+        }
+        Some(path) => path.clone(),
+        None => git_root.join(config::CONFIG_FILE_NAME),
+    };
+
+    let flake = NixFlake::from_git_root(&git_root)?;
+    let available_attrs = discover_attributes(&flake, no_cache).await?;
+    let contents = init::scaffold_config(&available_attrs);
+
+    let current = match &existing {
+        Some(path) => std::fs::read_to_string(path)?,
+        None => String::new(),
+    };
+    let label = target
+        .strip_prefix(&git_root)
+        .unwrap_or(&target)
+        .display()
+        .to_string();
+
+    if current == contents {
+        cli::print_success(&format!("{} is already up to date", label));
+        return Ok(());
+    }
+
+    cli::print_file_diff(&label, &current, &contents);
+    println!();
+
+    if dry_run {
+        cli::print_info("dry-run: nothing written");
+        return Ok(());
+    }
+    if existing.is_some() && !force {
+        return Err(GarnixError::Config(format!(
+            "{} already exists, pass --force to overwrite it",
+            label
+        )));
+    }
+
+    std::fs::write(&target, &contents)?;
+    cli::print_success(&format!(
+        "wrote {} covering {} attributes",
+        label,
+        available_attrs.len()
+    ));
+
+    Ok(())
+}
+
+fn run_config_show(overrides: &[PathBuf], effective: bool, branch: Option<String>) -> Result<()> {
+    let git_root = git::get_git_root()?;
+    let layers = config::read_layers(&git_root, overrides)?;
+
+    if !effective {
+        if layers.is_empty() {
+            cli::print_info("no garnix config found, defaults apply (see --effective)");
+        }
+        for (index, layer) in layers.iter().enumerate() {
+            if index > 0 {
+                println!();
+            }
+            println!("# {}", layer.path.display());
+            print!("{}", layer.contents);
+        }
+        return Ok(());
+    }
+
+    let branch = match branch {
+        Some(branch) => Sourced::new(branch, Source::Flag("--branch")),
+        None => Sourced::new(git::get_current_branch()?, Source::CurrentBranch),
+    };
+    let resolved = config::effective::resolve(&layers, &git_root, branch)?;
+    print!("{}", config::effective::render(&resolved));
+
+    Ok(())
+}
+
+/// The file `config fmt` / `config lint` work on: `path`, or the repository's config file.
+fn config_file_or_default(path: Option<PathBuf>) -> Result<PathBuf> {
+    match path {
+        Some(path) => Ok(path),
+        None => {
+            let git_root = git::get_git_root()?;
+            config::find_config_file(&git_root)?
+                .ok_or_else(|| GarnixError::Config("no garnix config found".to_string()))
+        }
+    }
+}
+
+fn run_config_lint(path: Option<PathBuf>, allow: &[String]) -> Result<()> {
+    let allowed = allow
+        .iter()
+        .map(|rule| {
+            Rule::parse(rule)
+                .ok_or_else(|| GarnixError::Config(format!("unknown lint rule `{}`", rule)))
+        })
+        .collect::<Result<Vec<Rule>>>()?;
+
+    let path = config_file_or_default(path)?;
+    let label = path.display().to_string();
+    let config = config::load_config(&path)?
+        .ok_or_else(|| GarnixError::Config(format!("config file not found: {}", label)))?;
+    let contents = std::fs::read_to_string(&path)?;
+
+    // Without a repository the branch rule has nothing to check against.
+    let branches = git::get_git_root().ok().and_then(|git_root| {
+        let mut branches = git::list_branches(git_root).ok()?;
+        branches.extend(git::get_current_branch().ok());
+        Some(branches)
+    });
+
+    let findings: Vec<_> = config::lint::lint(
+        &config.builds,
+        &LineMap::parse(&contents),
+        branches.as_deref(),
+    )
+    .into_iter()
+    .filter(|finding| !allowed.contains(&finding.rule))
+    .collect();
+
+    for finding in &findings {
+        let location = match finding.line {
+            Some(line) => format!("{}:{}", label, line),
+            None => label.clone(),
+        };
+        let message = format!("{}: {}: {}", location, finding.rule, finding.message);
+        if finding.rule.is_note() {
+            cli::print_info(&format!("note: {}", message));
+        } else {
+            cli::print_warning(&message);
+        }
+    }
+
+    let problems = findings
+        .iter()
+        .filter(|finding| !finding.rule.is_note())
+        .count();
+    if problems == 0 {
+        cli::print_success(&if findings.is_empty() {
+            format!("{}: no lint findings", label)
+        } else {
+            format!("{}: only notes, nothing to fix", label)
+        });
+        return Ok(());
+    }
+
+    Err(GarnixError::Config(format!(
+        "{} lint findings in {}",
+        problems, label
+    )))
+}
+
+fn run_config_fmt(path: Option<PathBuf>, style: BuildsStyle, check: bool) -> Result<()> {
+    let path = config_file_or_default(path)?;
+    if config::ConfigFormat::from_path(&path) == config::ConfigFormat::Json {
+        return Err(GarnixError::Config(format!(
+            "{} is JSON, `garnix config fmt` only formats YAML",
+            path.display()
+        )));
+    }
+
+    let label = path.display().to_string();
+    let contents = std::fs::read_to_string(&path)?;
+    let formatted = config::fmt::format_config(&contents, &label, style)?;
+
+    if formatted == contents {
+        cli::print_success(&format!("{} is already formatted", label));
+        return Ok(());
+    }
+
+    if check {
+        cli::print_file_diff(&label, &contents, &formatted);
+        return Err(GarnixError::Config(format!(
+            "{} is not formatted, run `garnix config fmt`",
+            label
+        )));
+    }
+
+    std::fs::write(&path, &formatted)?;
+    cli::print_success(&format!("formatted {}", label));
+    Ok(())
+}
+
+async fn run_doctor() -> Result<()> {
+    let checks = doctor::run_checks().await;
+    for check in &checks {
+        cli::print_check(check);
+    }
+
+    let failed = checks
+        .iter()
+        .filter(|check| check.status == CheckStatus::Failed)
+        .count();
+
+    println!();
+    if failed > 0 {
+        return Err(GarnixError::Environment(format!(
+            "{} environment checks failed",
+            failed
+        )));
     }
 
+    cli::print_success("everything looks good");
     Ok(())
 }
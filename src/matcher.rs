@@ -1,6 +1,7 @@
 use crate::config::{BuildEntry, BuildsConfig, GarnixConfig};
 use crate::error::{GarnixError, Result};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
 
 pub struct AttributeMatcher {
     current_branch: String,
@@ -11,6 +12,10 @@ impl AttributeMatcher {
         Self { current_branch }
     }
 
+    /// Attributes the config builds on the current branch. Each entry builds
+    /// what its includes match minus what its own excludes match, so an
+    /// exclude never removes another entry's attributes and entry order
+    /// doesn't matter.
     pub fn get_matching_attributes(
         &self,
         config: &Option<GarnixConfig>,
@@ -20,20 +25,119 @@ impl AttributeMatcher {
             return Ok(Vec::new());
         };
 
-        let mut matched_attrs = HashSet::new();
-
         let applicable_entries = self.get_applicable_build_entries(&config.builds);
 
-        for entry in applicable_entries {
-            self.apply_include_patterns(&entry.include, available_attrs, &mut matched_attrs)?;
-            self.apply_exclude_patterns(&entry.exclude, available_attrs, &mut matched_attrs)?;
+        let mut result = Vec::new();
+        for attr in available_attrs {
+            for entry in &applicable_entries {
+                if self.entry_builds(entry, attr)? {
+                    result.push(attr.clone());
+                    break;
+                }
+            }
         }
 
-        let mut result: Vec<String> = matched_attrs.into_iter().collect();
         result.sort();
+        result.dedup();
+        Ok(result)
+    }
+
+    /// Attributes that are built only because excludes no longer reach
+    /// across entries. garnix-cli used to apply every entry's includes and
+    /// then its excludes, in order, to one shared set, letting an exclude
+    /// remove what another entry includes. Entry-scoped excludes only ever
+    /// remove less, so this is the whole difference.
+    pub fn get_attributes_no_longer_excluded(
+        &self,
+        config: &Option<GarnixConfig>,
+        available_attrs: &[String],
+    ) -> Result<Vec<String>> {
+        let scoped = self.get_matching_attributes(config, available_attrs)?;
+        let Some(config) = config else {
+            return Ok(scoped);
+        };
+
+        let mut sequential = HashSet::new();
+        for entry in self.get_applicable_build_entries(&config.builds) {
+            self.apply_include_patterns(&entry.include, available_attrs, &mut sequential)?;
+            self.apply_exclude_patterns(&entry.exclude, available_attrs, &mut sequential)?;
+        }
+
+        Ok(scoped
+            .into_iter()
+            .filter(|attr| !sequential.contains(attr))
+            .collect())
+    }
+
+    /// Narrows an already matched set to attributes matching any `only` pattern
+    /// (when given) and no `skip` pattern.
+    pub fn filter_attributes(
+        &self,
+        attrs: &[String],
+        only: &[String],
+        skip: &[String],
+    ) -> Result<Vec<String>> {
+        let mut result = Vec::new();
+
+        for attr in attrs {
+            if !only.is_empty() && !self.matches_any_pattern(only, attr)? {
+                continue;
+            }
+            if self.matches_any_pattern(skip, attr)? {
+                continue;
+            }
+            result.push(attr.clone());
+        }
+
         Ok(result)
     }
 
+    /// Timeouts from the applicable build entries for each attribute they
+    /// include. When several entries include an attribute the shortest wins.
+    pub fn get_attribute_timeouts(
+        &self,
+        config: &Option<GarnixConfig>,
+        attrs: &[String],
+    ) -> Result<HashMap<String, Duration>> {
+        let mut timeouts: HashMap<String, Duration> = HashMap::new();
+        let Some(config) = config else {
+            return Ok(timeouts);
+        };
+
+        for entry in self.get_applicable_build_entries(&config.builds) {
+            let Some(timeout) = entry.timeout else {
+                continue;
+            };
+
+            for attr in attrs {
+                if self.entry_builds(entry, attr)? {
+                    timeouts
+                        .entry(attr.clone())
+                        .and_modify(|existing| *existing = (*existing).min(timeout))
+                        .or_insert(timeout);
+                }
+            }
+        }
+
+        Ok(timeouts)
+    }
+
+    /// Whether `entry` builds `attr`: one of its includes matches and none of
+    /// its excludes do.
+    fn entry_builds(&self, entry: &BuildEntry, attr: &str) -> Result<bool> {
+        Ok(self.matches_any_pattern(&entry.include, attr)?
+            && !self.matches_any_pattern(&entry.exclude, attr)?)
+    }
+
+    fn matches_any_pattern(&self, patterns: &[String], attr: &str) -> Result<bool> {
+        for pattern in patterns {
+            if self.matches_pattern(pattern, attr)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
     fn get_applicable_build_entries<'a>(&self, builds: &'a BuildsConfig) -> Vec<&'a BuildEntry> {
         builds
             .entries()
@@ -104,6 +208,30 @@ impl AttributeMatcher {
     }
 }
 
+/// Whether every attribute matched by `narrow` is also matched by `broad`.
+pub fn pattern_covers(broad: &str, narrow: &str) -> bool {
+    let broad_parts: Vec<&str> = broad.split('.').collect();
+    let narrow_parts: Vec<&str> = narrow.split('.').collect();
+
+    broad_parts.len() == narrow_parts.len()
+        && broad_parts
+            .iter()
+            .zip(&narrow_parts)
+            .all(|(broad_part, narrow_part)| *broad_part == "*" || broad_part == narrow_part)
+}
+
+/// Whether some attribute could match both patterns.
+pub fn patterns_overlap(a: &str, b: &str) -> bool {
+    let a_parts: Vec<&str> = a.split('.').collect();
+    let b_parts: Vec<&str> = b.split('.').collect();
+
+    a_parts.len() == b_parts.len()
+        && a_parts
+            .iter()
+            .zip(&b_parts)
+            .all(|(a_part, b_part)| *a_part == "*" || *b_part == "*" || a_part == b_part)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -182,18 +310,21 @@ mod tests {
             include: vec!["packages.*.*".to_string()],
             exclude: vec![],
             branch: Some("main".to_string()),
+            timeout: None,
         };
 
         let dev_entry = BuildEntry {
             include: vec!["checks.*.*".to_string()],
             exclude: vec![],
             branch: Some("dev".to_string()),
+            timeout: None,
         };
 
         let no_branch_entry = BuildEntry {
             include: vec!["devShells.*".to_string()],
             exclude: vec![],
             branch: None,
+            timeout: None,
         };
 
         let builds = BuildsConfig::Multiple(vec![main_entry, dev_entry, no_branch_entry]);
@@ -216,4 +347,189 @@ mod tests {
                 .any(|e| e.include.contains(&"checks.*.*".to_string()))
         );
     }
+
+    /// (config, attributes built on main, attributes only built since
+    /// excludes became entry-scoped)
+    type PrecedenceCase = (&'static str, Vec<&'static str>, Vec<&'static str>);
+
+    #[test]
+    fn test_precedence_across_entries() {
+        let available: Vec<String> = [
+            "checks.x86_64-linux.fmt",
+            "checks.x86_64-linux.slow",
+            "packages.x86_64-linux.hello",
+            "packages.x86_64-linux.slow",
+        ]
+        .iter()
+        .map(|attr| attr.to_string())
+        .collect();
+
+        let cases: Vec<PrecedenceCase> = vec![
+            (
+                // Single entry: include minus exclude.
+                "builds:\n  include: [\"*.*.*\"]\n  exclude: [\"*.*.slow\"]\n",
+                vec!["checks.x86_64-linux.fmt", "packages.x86_64-linux.hello"],
+                vec![],
+            ),
+            (
+                // An exclude doesn't remove what a later entry includes...
+                "builds:\n  - include: [\"packages.*.*\"]\n    exclude: [\"packages.*.slow\"]\n  - include: [\"packages.*.slow\"]\n",
+                vec!["packages.x86_64-linux.hello", "packages.x86_64-linux.slow"],
+                vec![],
+            ),
+            (
+                // ...nor what an earlier entry includes.
+                "builds:\n  - include: [\"packages.*.*\"]\n  - include: [\"checks.*.*\"]\n    exclude: [\"*.*.slow\"]\n",
+                vec![
+                    "checks.x86_64-linux.fmt",
+                    "packages.x86_64-linux.hello",
+                    "packages.x86_64-linux.slow",
+                ],
+                vec!["packages.x86_64-linux.slow"],
+            ),
+            (
+                // So entry order doesn't matter.
+                "builds:\n  - include: [\"checks.*.*\"]\n    exclude: [\"*.*.slow\"]\n  - include: [\"packages.*.*\"]\n",
+                vec![
+                    "checks.x86_64-linux.fmt",
+                    "packages.x86_64-linux.hello",
+                    "packages.x86_64-linux.slow",
+                ],
+                vec![],
+            ),
+            (
+                // An exclude-only entry builds the default includes minus
+                // its excludes, and removes nothing from the others.
+                "builds:\n  - include: [\"packages.*.*\"]\n  - exclude: [\"packages.*.slow\"]\n",
+                vec![
+                    "checks.x86_64-linux.fmt",
+                    "checks.x86_64-linux.slow",
+                    "packages.x86_64-linux.hello",
+                    "packages.x86_64-linux.slow",
+                ],
+                vec!["packages.x86_64-linux.slow"],
+            ),
+            (
+                // Each exclude only narrows its own entry.
+                "builds:\n  - include: [\"packages.*.*\"]\n    exclude: [\"*.*.slow\"]\n  - include: [\"*.*.slow\"]\n    exclude: [\"packages.*.*\"]\n",
+                vec!["checks.x86_64-linux.slow", "packages.x86_64-linux.hello"],
+                vec!["packages.x86_64-linux.hello"],
+            ),
+            (
+                // Entries for other branches neither build nor exclude.
+                "builds:\n  - include: [\"checks.*.*\"]\n  - include: [\"*.*.*\"]\n    exclude: [\"checks.*.*\"]\n    branch: dev\n",
+                vec!["checks.x86_64-linux.fmt", "checks.x86_64-linux.slow"],
+                vec![],
+            ),
+        ];
+
+        let matcher = AttributeMatcher::new("main".to_string());
+        for (yaml, built, no_longer_excluded) in cases {
+            let config = Some(crate::config::parse_config(yaml).unwrap());
+            assert_eq!(
+                matcher
+                    .get_matching_attributes(&config, &available)
+                    .unwrap(),
+                built,
+                "config:\n{}",
+                yaml
+            );
+            assert_eq!(
+                matcher
+                    .get_attributes_no_longer_excluded(&config, &available)
+                    .unwrap(),
+                no_longer_excluded,
+                "config:\n{}",
+                yaml
+            );
+        }
+    }
+
+    #[test]
+    fn test_filter_attributes() {
+        let matcher = AttributeMatcher::new("main".to_string());
+        let attrs = vec![
+            "checks.x86_64-linux.fmt".to_string(),
+            "checks.x86_64-linux.test".to_string(),
+            "packages.x86_64-linux.hello".to_string(),
+        ];
+
+        let only_checks = matcher
+            .filter_attributes(&attrs, &["checks.*.*".to_string()], &[])
+            .unwrap();
+        assert_eq!(only_checks.len(), 2);
+
+        let skipped = matcher
+            .filter_attributes(
+                &attrs,
+                &["checks.*.*".to_string()],
+                &["checks.*.fmt".to_string()],
+            )
+            .unwrap();
+        assert_eq!(skipped, vec!["checks.x86_64-linux.test".to_string()]);
+
+        let unfiltered = matcher.filter_attributes(&attrs, &[], &[]).unwrap();
+        assert_eq!(unfiltered, attrs);
+    }
+
+    #[test]
+    fn test_attribute_timeouts() {
+        let matcher = AttributeMatcher::new("main".to_string());
+        let config = Some(
+            crate::config::parse_config(
+                r#"
+builds:
+  - include: ["checks.*.*"]
+    exclude: ["checks.*.fast"]
+    timeout: 30m
+  - include: ["checks.*.slow"]
+    timeout: 600
+  - include: ["packages.*.*"]
+"#,
+            )
+            .unwrap(),
+        );
+        let attrs = vec![
+            "checks.x86_64-linux.fast".to_string(),
+            "checks.x86_64-linux.slow".to_string(),
+            "checks.x86_64-linux.test".to_string(),
+            "packages.x86_64-linux.hello".to_string(),
+        ];
+
+        let timeouts = matcher.get_attribute_timeouts(&config, &attrs).unwrap();
+
+        assert_eq!(timeouts.len(), 2);
+        assert_eq!(
+            timeouts["checks.x86_64-linux.test"],
+            Duration::from_secs(1800)
+        );
+        assert_eq!(
+            timeouts["checks.x86_64-linux.slow"],
+            Duration::from_secs(600)
+        );
+    }
+
+    #[test]
+    fn test_pattern_covers() {
+        assert!(pattern_covers(
+            "packages.*.*",
+            "packages.x86_64-linux.hello"
+        ));
+        assert!(pattern_covers("packages.*.*", "packages.*.hello"));
+        assert!(pattern_covers("packages.*.*", "packages.*.*"));
+        assert!(!pattern_covers("packages.*.hello", "packages.*.*"));
+        assert!(!pattern_covers("packages.*", "packages.x86_64-linux.hello"));
+        assert!(!pattern_covers("checks.*.*", "packages.x86_64-linux.hello"));
+    }
+
+    #[test]
+    fn test_patterns_overlap() {
+        assert!(patterns_overlap(
+            "packages.*.hello",
+            "packages.x86_64-linux.*"
+        ));
+        assert!(patterns_overlap("*.x86_64-linux.*", "checks.*.fmt"));
+        assert!(!patterns_overlap("packages.*.hello", "packages.*.world"));
+        assert!(!patterns_overlap("packages.*", "packages.*.*"));
+    }
 }
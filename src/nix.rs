@@ -1,11 +1,470 @@
+use crate::cache::AttributeCache;
 use crate::error::{GarnixError, Result};
+use crate::logs::LogStore;
+use crate::progress::{self, BuildStats, ProgressTracker};
+use crate::signal::{self, CancelToken};
+use clap::ValueEnum;
 use serde_json::Value;
-use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
 use tokio::process::Command;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
 
+/// Flake outputs keyed by name rather than by system, so a key like
+/// `nixosConfigurations.x86_64-builder` isn't another system's output.
+const NAMED_OUTPUT_CATEGORIES: [&str; 8] = [
+    "darwinConfigurations",
+    "darwinModules",
+    "homeConfigurations",
+    "homeModules",
+    "nixosConfigurations",
+    "nixosModules",
+    "overlays",
+    "templates",
+];
+
+/// Environment variable overriding the nix binary, e.g. a wrapper script or `lix`.
+pub const NIX_BIN_ENV: &str = "GARNIX_NIX_BIN";
+
+#[derive(Clone)]
 pub struct NixFlake {
     pub flake_path: String,
+    pub nix_bin: String,
+    pub builder: Builder,
+    pub eval_backend: EvalBackend,
+    pub eval_workers: usize,
+    pub build_jobs: usize,
+    version: OnceLock<Option<NixVersion>>,
+}
+
+/// What evaluates matched attributes before they are built.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum EvalBackend {
+    /// one `nix eval` per attribute
+    Nix,
+    /// nix-eval-jobs, evaluating each attribute set in parallel
+    NixEvalJobs,
+    /// nix-eval-jobs if it is installed, nix otherwise
+    Auto,
+}
+
+/// Which program drives `build`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Builder {
+    /// nix itself, with garnix rendering progress
+    Nix,
+    /// nix-output-monitor
+    Nom,
+    /// nom if it is installed, nix otherwise
+    Auto,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NixImplementation {
+    Nix,
+    Lix,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NixVersion {
+    pub implementation: NixImplementation,
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl NixVersion {
+    /// Parses `nix --version` output such as `nix (Nix) 2.18.1` or `nix (Lix, like Nix) 2.91.1`.
+    pub fn parse(output: &str) -> Option<Self> {
+        let line = output.lines().next()?;
+        let implementation = if line.contains("Lix") {
+            NixImplementation::Lix
+        } else {
+            NixImplementation::Nix
+        };
+
+        let version = line.split_whitespace().last()?;
+        let mut parts = version
+            .split(|c: char| !c.is_ascii_digit())
+            .filter(|part| !part.is_empty())
+            .map(|part| part.parse::<u32>());
+
+        Some(Self {
+            implementation,
+            major: parts.next()?.ok()?,
+            minor: parts.next()?.ok()?,
+            patch: parts.next().and_then(|part| part.ok()).unwrap_or(0),
+        })
+    }
+
+    fn at_least(&self, major: u32, minor: u32) -> bool {
+        (self.major, self.minor) >= (major, minor)
+    }
+
+    /// Flakes and the `nix` CLI garnix drives first shipped in nix 2.4.
+    pub fn supports_flakes(&self) -> bool {
+        self.implementation == NixImplementation::Lix || self.at_least(2, 4)
+    }
+
+    /// `--log-format internal-json` arrived together with the new CLI in nix 2.4.
+    pub fn supports_internal_json_log(&self) -> bool {
+        self.implementation == NixImplementation::Lix || self.at_least(2, 4)
+    }
+}
+
+impl std::fmt::Display for NixVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self.implementation {
+            NixImplementation::Nix => "nix",
+            NixImplementation::Lix => "lix",
+        };
+        write!(f, "{} {}.{}.{}", name, self.major, self.minor, self.patch)
+    }
+}
+
+pub fn nix_bin_from_env() -> String {
+    std::env::var(NIX_BIN_ENV)
+        .ok()
+        .filter(|bin| !bin.is_empty())
+        .unwrap_or_else(|| "nix".to_string())
+}
+
+pub async fn detect_nix_version(nix_bin: &str) -> Option<NixVersion> {
+    let output = Command::new(nix_bin)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .output()
+        .await
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    NixVersion::parse(&String::from_utf8_lossy(&output.stdout))
+}
+
+pub fn check_version(version: NixVersion) -> Result<()> {
+    if version.supports_flakes() {
+        Ok(())
+    } else {
+        Err(GarnixError::Environment(format!(
+            "{} is too old for flakes, garnix needs nix 2.4 or newer",
+            version
+        )))
+    }
+}
+
+/// Experimental features that have to be enabled for garnix to work.
+pub const REQUIRED_EXPERIMENTAL_FEATURES: [&str; 2] = ["nix-command", "flakes"];
+
+/// Returns the required experimental features that aren't enabled in the nix config.
+pub async fn missing_experimental_features(nix_bin: &str) -> Result<Vec<String>> {
+    let mut last_error = String::new();
+
+    // `nix config show` replaced `nix show-config` in 2.20; try both.
+    let commands: [&[&str]; 2] = [&["config", "show", "--json"], &["show-config", "--json"]];
+    for args in commands {
+        let output = Command::new(nix_bin)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .output()
+            .await
+            .map_err(spawn_error(nix_bin))?;
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if output.status.success() {
+            let json: Value = serde_json::from_slice(&output.stdout)?;
+            return Ok(missing_features(&enabled_experimental_features(&json)));
+        }
+        if stderr.contains("'nix-command' is disabled") {
+            // Without nix-command we can't read the config at all, so assume the worst.
+            return Ok(REQUIRED_EXPERIMENTAL_FEATURES
+                .iter()
+                .map(|feature| feature.to_string())
+                .collect());
+        }
+        last_error = stderr.trim().to_string();
+    }
+
+    Err(GarnixError::NixCommand(format!(
+        "could not read the nix configuration: {}",
+        last_error
+    )))
+}
+
+fn enabled_experimental_features(config: &Value) -> Vec<String> {
+    config
+        .get("experimental-features")
+        .and_then(|setting| setting.get("value"))
+        .and_then(Value::as_array)
+        .map(|features| {
+            features
+                .iter()
+                .filter_map(Value::as_str)
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn missing_features(enabled: &[String]) -> Vec<String> {
+    REQUIRED_EXPERIMENTAL_FEATURES
+        .iter()
+        .filter(|feature| !enabled.iter().any(|enabled| enabled == *feature))
+        .map(|feature| feature.to_string())
+        .collect()
+}
+
+/// Experimental features nix refused to use, from errors like
+/// `experimental Nix feature 'flakes' is disabled`.
+fn disabled_experimental_features(stderr: &str) -> Vec<String> {
+    const MARKER: &str = "experimental Nix feature '";
+
+    let mut disabled: Vec<String> = Vec::new();
+    let mut rest = stderr;
+    while let Some(start) = rest.find(MARKER) {
+        rest = &rest[start + MARKER.len()..];
+        let Some(end) = rest.find('\'') else {
+            break;
+        };
+        if !disabled.iter().any(|feature| feature == &rest[..end]) {
+            disabled.push(rest[..end].to_string());
+        }
+        rest = &rest[end..];
+    }
+    disabled
+}
+
+/// Explains how to turn on the given experimental features.
+pub fn experimental_features_help(missing: &[String]) -> String {
+    format!(
+        "nix experimental features not enabled: {missing}\n\
+         enable them by adding this line to ~/.config/nix/nix.conf (or /etc/nix/nix.conf):\n\
+         \x20   experimental-features = nix-command flakes\n\
+         on NixOS, set `nix.settings.experimental-features = [ \"nix-command\" \"flakes\" ];` instead",
+        missing = missing.join(", ")
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheStatus {
+    /// All outputs are already in the local store.
+    Cached,
+    /// Nothing needs building, but some paths have to be fetched from a substituter.
+    WillSubstitute,
+    WillBuild,
+}
+
+impl std::fmt::Display for CacheStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CacheStatus::Cached => write!(f, "cached"),
+            CacheStatus::WillSubstitute => write!(f, "will substitute"),
+            CacheStatus::WillBuild => write!(f, "will build"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildOutcome {
+    Succeeded,
+    Failed,
+    /// The attribute didn't evaluate, so it was never built.
+    EvalFailed,
+    /// Killed after exceeding its timeout.
+    TimedOut,
+    /// Interrupted by Ctrl-C/SIGTERM, or never started because of it.
+    Cancelled,
+}
+
+/// How `build_attributes` runs each build.
+#[derive(Debug, Clone, Copy)]
+struct BuildMode {
+    nom: bool,
+    internal_json: bool,
+    /// Prefix output with the attribute, since builds run concurrently.
+    labelled: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct BuildResult {
+    pub attribute: String,
+    pub outcome: BuildOutcome,
+    pub log_path: PathBuf,
+    /// `None` if nom ran the build, or it never started.
+    pub stats: Option<BuildStats>,
+}
+
+/// Result of evaluating an attribute's derivation before building it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Evaluation {
+    Evaluated { drv_path: String },
+    Failed { error: String },
+}
+
+#[derive(Debug, Clone)]
+pub struct EvaluatedAttribute {
+    pub attribute: String,
+    pub evaluation: Evaluation,
+}
+
+#[derive(Debug, Clone)]
+pub struct AttributeStatus {
+    pub attribute: String,
+    pub status: CacheStatus,
+    pub plan: DryRunPlan,
+}
+
+/// Store paths `nix build --dry-run` reports it would build or fetch.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DryRunPlan {
+    pub will_build: Vec<String>,
+    pub will_fetch: Vec<String>,
+    pub download_bytes: u64,
+    pub unpacked_bytes: u64,
+    /// Combined from plans that fetch some of the same paths, whose sizes
+    /// were counted once per plan: the byte counts are an upper bound.
+    pub shared_fetches: bool,
+}
+
+impl DryRunPlan {
+    pub fn parse(stderr: &str) -> Self {
+        enum Section {
+            None,
+            Build,
+            Fetch,
+        }
+
+        let mut plan = DryRunPlan::default();
+        let mut section = Section::None;
+
+        for line in stderr.lines() {
+            let trimmed = line.trim();
+            if trimmed.contains("will be built") {
+                section = Section::Build;
+            } else if trimmed.contains("will be fetched") {
+                section = Section::Fetch;
+                if let Some((download, unpacked)) = parse_fetch_sizes(trimmed) {
+                    plan.download_bytes += download;
+                    plan.unpacked_bytes += unpacked;
+                }
+            } else if trimmed.starts_with('/') && line.starts_with(char::is_whitespace) {
+                match section {
+                    Section::Build => plan.will_build.push(trimmed.to_string()),
+                    Section::Fetch => plan.will_fetch.push(trimmed.to_string()),
+                    Section::None => {}
+                }
+            } else {
+                section = Section::None;
+            }
+        }
+
+        plan
+    }
+
+    /// Merges per-attribute plans, listing paths several attributes share
+    /// once. nix only reports sizes per plan, so shared fetches can't be
+    /// subtracted from the totals.
+    pub fn combine<'a>(plans: impl IntoIterator<Item = &'a DryRunPlan>) -> Self {
+        let mut combined = DryRunPlan::default();
+        let mut seen = HashSet::new();
+
+        for plan in plans {
+            for drv in &plan.will_build {
+                if seen.insert(drv) {
+                    combined.will_build.push(drv.clone());
+                }
+            }
+            for path in &plan.will_fetch {
+                if seen.insert(path) {
+                    combined.will_fetch.push(path.clone());
+                } else {
+                    combined.shared_fetches = true;
+                }
+            }
+            combined.download_bytes += plan.download_bytes;
+            combined.unpacked_bytes += plan.unpacked_bytes;
+            combined.shared_fetches |= plan.shared_fetches;
+        }
+
+        combined
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.will_build.is_empty() && self.will_fetch.is_empty()
+    }
+
+    pub fn cache_status(&self) -> CacheStatus {
+        if !self.will_build.is_empty() {
+            CacheStatus::WillBuild
+        } else if !self.will_fetch.is_empty() {
+            CacheStatus::WillSubstitute
+        } else {
+            CacheStatus::Cached
+        }
+    }
+}
+
+/// Parses the `(1.50 MiB download, 6.20 MiB unpacked)` suffix of a fetch header.
+fn parse_fetch_sizes(header: &str) -> Option<(u64, u64)> {
+    let start = header.find('(')?;
+    let end = header.rfind(')')?;
+    let mut download = None;
+    let mut unpacked = None;
+
+    for part in header[start + 1..end].split(',') {
+        let mut words = part.split_whitespace();
+        let bytes = parse_size(words.next()?, words.next()?)?;
+        match words.next()? {
+            "download" => download = Some(bytes),
+            "unpacked" => unpacked = Some(bytes),
+            _ => {}
+        }
+    }
+
+    Some((download.unwrap_or(0), unpacked.unwrap_or(0)))
+}
+
+fn parse_size(amount: &str, unit: &str) -> Option<u64> {
+    let amount: f64 = amount.parse().ok()?;
+    let multiplier = match unit {
+        "B" | "bytes" => 1.0,
+        "KiB" => 1024.0,
+        "MiB" => 1024.0 * 1024.0,
+        "GiB" => 1024.0 * 1024.0 * 1024.0,
+        "TiB" => 1024.0 * 1024.0 * 1024.0 * 1024.0,
+        _ => return None,
+    };
+    Some((amount * multiplier).round() as u64)
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.2} {}", size, UNITS[unit])
 }
 
 impl NixFlake {
@@ -18,6 +477,12 @@ impl NixFlake {
 
         Ok(Self {
             flake_path: flake_path.to_string_lossy().to_string(),
+            nix_bin: nix_bin_from_env(),
+            builder: Builder::Nix,
+            eval_backend: EvalBackend::Auto,
+            eval_workers: cpu_count(),
+            build_jobs: cpu_count(),
+            version: OnceLock::new(),
         })
     }
 
@@ -25,16 +490,109 @@ impl NixFlake {
         Self::new(git_root)
     }
 
+    pub fn with_builder(mut self, builder: Builder) -> Self {
+        self.builder = builder;
+        self
+    }
+
+    pub fn with_build_jobs(mut self, jobs: Option<usize>) -> Self {
+        if let Some(jobs) = jobs {
+            self.build_jobs = jobs.max(1);
+        }
+        self
+    }
+
+    pub fn with_eval_backend(mut self, backend: EvalBackend, workers: Option<usize>) -> Self {
+        self.eval_backend = backend;
+        if let Some(workers) = workers {
+            self.eval_workers = workers.max(1);
+        }
+        self
+    }
+
+    /// The detected nix version, or `None` if `nix --version` couldn't be parsed.
+    pub async fn version(&self) -> Option<NixVersion> {
+        if let Some(version) = self.version.get() {
+            return *version;
+        }
+
+        let version = detect_nix_version(&self.nix_bin).await;
+        let _ = self.version.set(version);
+        version
+    }
+
+    /// Checks that nix is installed and new enough for flakes. Experimental
+    /// features aren't checked up front: they can be enabled in ways
+    /// `nix config show` doesn't list (NIX_CONFIG, flags, Determinate Nix),
+    /// so `discover_attributes` explains them once nix actually complains.
+    pub async fn preflight(&self) -> Result<NixVersion> {
+        let output = Command::new(&self.nix_bin)
+            .arg("--version")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .output()
+            .await
+            .map_err(spawn_error(&self.nix_bin))?;
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let version = NixVersion::parse(&stdout).ok_or_else(|| {
+            GarnixError::Environment(format!(
+                "could not determine the nix version from `{} --version`: {}",
+                self.nix_bin,
+                stdout.trim()
+            ))
+        })?;
+
+        check_version(version)?;
+
+        Ok(version)
+    }
+
+    /// Unknown versions are assumed to be recent.
+    async fn supports(&self, feature: fn(&NixVersion) -> bool) -> bool {
+        self.version().await.as_ref().is_none_or(feature)
+    }
+
     pub async fn discover_attributes(&self) -> Result<Vec<String>> {
-        let output = Command::new("nix")
+        let current_system = self.get_current_system().await?;
+        self.discover_attributes_for_system(&current_system).await
+    }
+
+    /// Like `discover_attributes`, but reuses a cached result when the flake
+    /// source, `flake.lock` and current system are unchanged.
+    pub async fn discover_attributes_cached(&self, cache: &AttributeCache) -> Result<Vec<String>> {
+        let current_system = self.get_current_system().await?;
+        let key = AttributeCache::key_for(&self.flake_path, &current_system)?;
+
+        if let Some(attributes) = cache.load(&key) {
+            return Ok(attributes);
+        }
+
+        let attributes = self.discover_attributes_for_system(&current_system).await?;
+        // A cache we can't write to shouldn't stop the build, it just won't speed up the next one.
+        let _ = cache.store(&key, &attributes);
+
+        Ok(attributes)
+    }
+
+    async fn discover_attributes_for_system(&self, current_system: &str) -> Result<Vec<String>> {
+        let output = Command::new(&self.nix_bin)
             .args(["flake", "show", "--json", &self.flake_path])
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .output()
-            .await?;
+            .await
+            .map_err(spawn_error(&self.nix_bin))?;
 
         if !output.status.success() {
             let stderr = String::from_utf8_lossy(&output.stderr);
+            let disabled = disabled_experimental_features(&stderr);
+            if !disabled.is_empty() {
+                return Err(GarnixError::Environment(experimental_features_help(
+                    &disabled,
+                )));
+            }
             return Err(GarnixError::NixCommand(format!(
                 "nix flake show failed: {}",
                 stderr
@@ -45,19 +603,19 @@ impl NixFlake {
         let json: Value = serde_json::from_str(&stdout)?;
 
         let mut attributes = Vec::new();
-        let current_system = self.get_current_system().await?;
-        self.extract_attributes(&json, Vec::new(), &mut attributes, &current_system);
+        self.extract_attributes(&json, Vec::new(), &mut attributes, current_system);
 
         Ok(attributes)
     }
 
     async fn get_current_system(&self) -> Result<String> {
-        let output = Command::new("nix")
+        let output = Command::new(&self.nix_bin)
             .args(["eval", "--expr", "builtins.currentSystem", "--impure"])
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .output()
-            .await?;
+            .await
+            .map_err(spawn_error(&self.nix_bin))?;
 
         if !output.status.success() {
             return Ok("x86_64-linux".to_string());
@@ -84,6 +642,7 @@ impl NixFlake {
                 new_path.push(key.clone());
 
                 if path.len() == 1
+                    && !NAMED_OUTPUT_CATEGORIES.contains(&path[0].as_str())
                     && !key.is_empty()
                     && key != current_system
                     && key.contains("-")
@@ -117,48 +676,571 @@ impl NixFlake {
         }
     }
 
-    pub async fn build_attributes(&self, attributes: &[String], dry_run: bool) -> Result<()> {
+    /// Evaluates each attribute's `drvPath` on its own, so one attribute that
+    /// fails to evaluate doesn't take the others down with it.
+    pub async fn evaluate_attributes(
+        &self,
+        attributes: &[String],
+    ) -> Result<Vec<EvaluatedAttribute>> {
+        let use_eval_jobs = match self.eval_backend {
+            EvalBackend::Nix => false,
+            EvalBackend::NixEvalJobs if !nix_eval_jobs_available().await => {
+                return Err(GarnixError::NixCommand(
+                    "--eval-backend nix-eval-jobs was requested but nix-eval-jobs is not installed"
+                        .to_string(),
+                ));
+            }
+            EvalBackend::NixEvalJobs => true,
+            EvalBackend::Auto => nix_eval_jobs_available().await,
+        };
+
+        if use_eval_jobs {
+            self.evaluate_with_nix_eval_jobs(attributes).await
+        } else {
+            self.evaluate_with_nix(attributes).await
+        }
+    }
+
+    /// Runs nix-eval-jobs once, over an expression selecting just the matched
+    /// attributes, so the rest of their attribute sets isn't evaluated.
+    /// Configurations are selected through their toplevel derivation.
+    async fn evaluate_with_nix_eval_jobs(
+        &self,
+        attributes: &[String],
+    ) -> Result<Vec<EvaluatedAttribute>> {
         if attributes.is_empty() {
-            return Ok(());
+            return Ok(Vec::new());
         }
 
-        let use_nom = nom_available().await;
+        // `builtins.getFlake` only accepts an unlocked local path with --impure.
+        let mut child = Command::new("nix-eval-jobs")
+            .args([
+                "--impure",
+                "--expr",
+                &eval_jobs_expr(&self.flake_path, attributes),
+                "--workers",
+                &self.eval_workers.to_string(),
+            ])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(spawn_error("nix-eval-jobs"))?;
+
+        // Read stderr alongside stdout: once warnings or traces fill the pipe,
+        // nix-eval-jobs blocks and never closes stdout.
+        let stderr = child.stderr.take();
+        let stderr_reader = tokio::spawn(async move {
+            let mut output = Vec::new();
+            if let Some(mut stderr) = stderr {
+                let _ = stderr.read_to_end(&mut output).await;
+            }
+            String::from_utf8_lossy(&output).trim().to_string()
+        });
 
-        let bin = if use_nom { "nom" } else { "nix" };
+        let mut evaluations: HashMap<String, Evaluation> = HashMap::new();
+        if let Some(stdout) = child.stdout.take() {
+            let mut lines = BufReader::new(stdout).lines();
+            while let Some(line) = lines.next_line().await? {
+                let Some((job, evaluation)) = parse_eval_jobs_line(&line) else {
+                    continue;
+                };
+                if let Some(attr) = eval_job_attribute(attributes, &job) {
+                    evaluations.insert(attr.clone(), evaluation);
+                }
+            }
+        }
+
+        child.wait().await?;
+        let stderr = stderr_reader.await.unwrap_or_default();
+
+        // Whatever nix-eval-jobs didn't report on failed along with the whole run.
+        Ok(attributes
+            .iter()
+            .map(|attr| EvaluatedAttribute {
+                attribute: attr.clone(),
+                evaluation: evaluations
+                    .remove(attr)
+                    .unwrap_or_else(|| Evaluation::Failed {
+                        error: if stderr.is_empty() {
+                            format!("nix-eval-jobs did not evaluate {}", attr)
+                        } else {
+                            stderr.clone()
+                        },
+                    }),
+            })
+            .collect())
+    }
 
-        let mut args = vec!["build".to_string()];
+    async fn evaluate_with_nix(&self, attributes: &[String]) -> Result<Vec<EvaluatedAttribute>> {
+        let mut evaluated = Vec::new();
 
         for attr in attributes {
-            let build_attr = transform_attribute_for_build(attr);
-            args.push(format!("{}#{}", self.flake_path, build_attr));
+            let output = Command::new(&self.nix_bin)
+                .args([
+                    "eval",
+                    "--raw",
+                    &format!("{}.drvPath", self.installable(attr)),
+                ])
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .output()
+                .await
+                .map_err(spawn_error(&self.nix_bin))?;
+
+            evaluated.push(EvaluatedAttribute {
+                attribute: attr.clone(),
+                evaluation: parse_nix_eval(
+                    output.status.success(),
+                    &String::from_utf8_lossy(&output.stdout),
+                    &String::from_utf8_lossy(&output.stderr),
+                ),
+            });
         }
 
-        let mut command = Command::new(bin);
-        command.args(&args);
-        if dry_run {
-            println!("dry-run: would execute:\n{:?}", command.as_std());
-        } else {
-            let mut child = command
-                .stdin(Stdio::null())
-                .stdout(Stdio::inherit())
-                .stderr(Stdio::inherit())
-                .spawn()?;
+        Ok(evaluated)
+    }
+
+    /// Asks nix which of the attributes' outputs are already in the local store,
+    /// would be substituted, or would have to be built. `DryRunPlan::combine`
+    /// turns the plans into one for the whole build.
+    pub async fn classify_attributes(&self, attributes: &[String]) -> Result<Vec<AttributeStatus>> {
+        let mut statuses = Vec::new();
 
-            let status = child.wait().await?;
+        for attr in attributes {
+            let output = Command::new(&self.nix_bin)
+                .args(["build", "--dry-run", "--no-link", &self.installable(attr)])
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .output()
+                .await
+                .map_err(spawn_error(&self.nix_bin))?;
 
-            if !status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if !output.status.success() {
                 return Err(GarnixError::NixCommand(format!(
-                    "{} build failed with exit code: {:?}",
-                    bin,
-                    status.code()
+                    "nix build --dry-run failed for {}: {}",
+                    attr, stderr
                 )));
             }
+
+            let plan = DryRunPlan::parse(&stderr);
+            statuses.push(AttributeStatus {
+                attribute: attr.clone(),
+                status: plan.cache_status(),
+                plan,
+            });
+        }
+
+        Ok(statuses)
+    }
+
+    fn installable(&self, attr: &str) -> String {
+        format!(
+            "{}#{}",
+            self.flake_path,
+            transform_attribute_for_build(attr)
+        )
+    }
+
+    /// Builds each attribute separately so that every attribute gets its own
+    /// log file and a failure doesn't stop the remaining builds. Up to
+    /// `build_jobs` builds run at once, with output prefixed by attribute.
+    pub async fn build_attributes(
+        &self,
+        attributes: &[String],
+        logs: &LogStore,
+        timeouts: &HashMap<String, Duration>,
+        cancel: &CancelToken,
+    ) -> Result<Vec<BuildResult>> {
+        // nom renders the internal-json log of our own nix binary.
+        let internal_json = self.supports(NixVersion::supports_internal_json_log).await;
+        let nom = match self.builder {
+            Builder::Nix => false,
+            Builder::Nom if !nom_available().await => {
+                return Err(GarnixError::NixCommand(
+                    "--builder nom was requested but nom is not installed".to_string(),
+                ));
+            }
+            Builder::Nom if !internal_json => {
+                return Err(GarnixError::Environment(format!(
+                    "--builder nom needs `--log-format internal-json`, which {} doesn't support",
+                    self.nix_bin
+                )));
+            }
+            Builder::Nom => true,
+            Builder::Auto => internal_json && nom_available().await,
+        };
+        // nom owns the terminal, so its builds can't share it.
+        let jobs = if nom { 1 } else { self.build_jobs };
+        let mode = BuildMode {
+            nom,
+            internal_json,
+            labelled: jobs > 1 && attributes.len() > 1,
+        };
+
+        let flake = Arc::new(self.clone());
+        let permits = Arc::new(Semaphore::new(jobs));
+        let mut builds = JoinSet::new();
+        for (index, attr) in attributes.iter().enumerate() {
+            let flake = flake.clone();
+            let permits = permits.clone();
+            let logs = logs.clone();
+            let attr = attr.clone();
+            let timeout = timeouts.get(&attr).copied();
+            let cancel = cancel.clone();
+            builds.spawn(async move {
+                let _permit = permits.acquire_owned().await;
+                let result = flake
+                    .build_attribute(&attr, &logs, timeout, mode, &cancel)
+                    .await;
+                (index, result)
+            });
+        }
+
+        let mut results = Vec::new();
+        while let Some(joined) = builds.join_next().await {
+            let (index, result) =
+                joined.map_err(|e| GarnixError::NixCommand(format!("build task failed: {}", e)))?;
+            results.push((index, result?));
+        }
+        results.sort_by_key(|(index, _)| *index);
+
+        Ok(results.into_iter().map(|(_, result)| result).collect())
+    }
+
+    async fn build_attribute(
+        &self,
+        attr: &str,
+        logs: &LogStore,
+        timeout: Option<Duration>,
+        mode: BuildMode,
+        cancel: &CancelToken,
+    ) -> Result<BuildResult> {
+        let log_path = logs.path_for(attr);
+
+        if cancel.is_cancelled() {
+            return Ok(BuildResult {
+                attribute: attr.to_string(),
+                outcome: BuildOutcome::Cancelled,
+                log_path,
+                stats: None,
+            });
+        }
+
+        let mut log_file = logs.create(attr)?;
+        let (outcome, stats) = if mode.nom {
+            let outcome = self.build_with_nom(attr, timeout, cancel).await?;
+            if outcome == BuildOutcome::Failed {
+                self.write_nix_log(attr, &mut log_file).await?;
+            }
+            (outcome, None)
+        } else {
+            let (outcome, stats) = self
+                .build_with_nix(attr, &mut log_file, mode, timeout, cancel)
+                .await?;
+            (outcome, Some(stats))
+        };
+
+        Ok(BuildResult {
+            attribute: attr.to_string(),
+            outcome,
+            log_path,
+            stats,
+        })
+    }
+
+    /// Runs `nix build` with internal-json logging, rendering progress on the
+    /// terminal while build logs go to the log file. Without internal-json
+    /// support the plain `-L` output is echoed and logged as is.
+    async fn build_with_nix(
+        &self,
+        attr: &str,
+        log_file: &mut File,
+        mode: BuildMode,
+        timeout: Option<Duration>,
+        cancel: &CancelToken,
+    ) -> Result<(BuildOutcome, BuildStats)> {
+        let installable = self.installable(attr);
+        let args = if mode.internal_json {
+            vec![
+                "build",
+                "--no-link",
+                "--log-format",
+                "internal-json",
+                &installable,
+            ]
+        } else {
+            vec!["build", "--no-link", "-L", &installable]
+        };
+        let prefix = if mode.labelled {
+            format!("{}> ", attr)
+        } else {
+            String::new()
+        };
+
+        let mut child = Command::new(&self.nix_bin)
+            .args(&args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(spawn_error(&self.nix_bin))?;
+
+        let mut tracker = ProgressTracker::new();
+        let stop = stop_requested(timeout, cancel);
+        tokio::pin!(stop);
+        let mut interrupted = None;
+        let mut kill_at = None;
+
+        if let Some(stderr) = child.stderr.take() {
+            let mut lines = BufReader::new(stderr).lines();
+            loop {
+                let line = tokio::select! {
+                    line = lines.next_line() => line?,
+                    outcome = &mut stop, if interrupted.is_none() => {
+                        // Keep draining output so nix can report how it wound down.
+                        signal::interrupt_child(child.id());
+                        interrupted = Some(outcome);
+                        kill_at = Some(Instant::now() + signal::KILL_GRACE_PERIOD);
+                        continue;
+                    }
+                    _ = signal::kill_due(kill_at, cancel), if interrupted.is_some() => {
+                        // nix didn't exit after being interrupted.
+                        child.start_kill()?;
+                        break;
+                    }
+                };
+                let Some(line) = line else {
+                    break;
+                };
+
+                let Some(event) = progress::parse_line(&line) else {
+                    eprintln!("{}{}", prefix, line);
+                    writeln!(log_file, "{}", line)?;
+                    continue;
+                };
+
+                match tracker.handle(event) {
+                    progress::Output::Log(line) => writeln!(log_file, "{}", line)?,
+                    progress::Output::Message(msg) => {
+                        eprintln!("{}{}", prefix, msg);
+                        writeln!(log_file, "{}", msg)?;
+                    }
+                    progress::Output::Status(status) => eprintln!("    {}{}", prefix, status),
+                    progress::Output::None => {}
+                }
+            }
+        }
+
+        let success = child.wait().await?.success();
+        Ok((
+            outcome_of(success, interrupted),
+            tracker.into_stats(success),
+        ))
+    }
+
+    /// Runs `nix build ... |& nom --json`, so the configured nix binary does
+    /// the build. `nom` owns the terminal, so its output isn't captured; the
+    /// log is recovered with `nix log` if the build fails.
+    async fn build_with_nom(
+        &self,
+        attr: &str,
+        timeout: Option<Duration>,
+        cancel: &CancelToken,
+    ) -> Result<BuildOutcome> {
+        let mut nix = Command::new(&self.nix_bin)
+            .args([
+                "build",
+                "--no-link",
+                "--log-format",
+                "internal-json",
+                "-v",
+                &self.installable(attr),
+            ])
+            .stdin(Stdio::null())
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(spawn_error(&self.nix_bin))?;
+
+        let log: Stdio = match nix.stderr.take() {
+            Some(stderr) => stderr.try_into()?,
+            None => Stdio::null(),
+        };
+        let mut nom = match Command::new("nom")
+            .arg("--json")
+            .stdin(log)
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .spawn()
+        {
+            Ok(nom) => nom,
+            Err(e) => {
+                nix.start_kill()?;
+                return Err(spawn_error("nom")(e));
+            }
+        };
+
+        let mut interrupted = None;
+        let status = tokio::select! {
+            status = nix.wait() => status?,
+            outcome = stop_requested(timeout, cancel) => {
+                interrupted = Some(outcome);
+                signal::stop_child(&mut nix, signal::KILL_GRACE_PERIOD, cancel).await?
+            }
+        };
+        // nom exits once nix closes its end of the pipe.
+        nom.wait().await?;
+
+        Ok(outcome_of(status.success(), interrupted))
+    }
+
+    async fn write_nix_log(&self, attr: &str, log_file: &mut File) -> Result<()> {
+        let output = Command::new(&self.nix_bin)
+            .args(["log", &self.installable(attr)])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .output()
+            .await
+            .map_err(spawn_error(&self.nix_bin))?;
+
+        log_file.write_all(&output.stdout)?;
+        if !output.status.success() {
+            log_file.write_all(&output.stderr)?;
         }
 
         Ok(())
     }
 }
 
+/// Resolves to `TimedOut` or `Cancelled`, whichever happens first.
+async fn stop_requested(timeout: Option<Duration>, cancel: &CancelToken) -> BuildOutcome {
+    let deadline = async {
+        match timeout {
+            Some(timeout) => tokio::time::sleep(timeout).await,
+            None => std::future::pending().await,
+        }
+    };
+
+    tokio::select! {
+        _ = deadline => BuildOutcome::TimedOut,
+        _ = cancel.cancelled() => BuildOutcome::Cancelled,
+    }
+}
+
+/// Reads the result of `nix eval --raw <attr>.drvPath`. Warnings on stderr
+/// don't fail a successful evaluation.
+fn parse_nix_eval(success: bool, stdout: &str, stderr: &str) -> Evaluation {
+    let drv_path = stdout.trim();
+    if !success {
+        Evaluation::Failed {
+            error: stderr.trim().to_string(),
+        }
+    } else if drv_path.is_empty() {
+        Evaluation::Failed {
+            error: "nix eval printed no derivation path".to_string(),
+        }
+    } else {
+        Evaluation::Evaluated {
+            drv_path: drv_path.to_string(),
+        }
+    }
+}
+
+/// The expression nix-eval-jobs evaluates: one job per attribute, named
+/// `a<index>` after its position in `attributes`.
+fn eval_jobs_expr(flake_path: &str, attributes: &[String]) -> String {
+    let mut expr = format!(
+        "let flake = builtins.getFlake {}; in {{\n",
+        nix_string(flake_path)
+    );
+    for (index, attr) in attributes.iter().enumerate() {
+        let path: Vec<String> = transform_attribute_for_build(attr)
+            .split('.')
+            .map(nix_string)
+            .collect();
+        expr.push_str(&format!("  a{} = flake.{};\n", index, path.join(".")));
+    }
+    expr.push('}');
+    expr
+}
+
+/// Quotes `value` as a nix string literal.
+fn nix_string(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace("${", "\\${");
+    format!("\"{}\"", escaped)
+}
+
+/// The attribute a job from `eval_jobs_expr` stands for.
+fn eval_job_attribute<'a>(attributes: &'a [String], job: &str) -> Option<&'a String> {
+    let index: usize = job.strip_prefix('a')?.parse().ok()?;
+    attributes.get(index)
+}
+
+/// Parses one line of nix-eval-jobs output into the job's name and its evaluation.
+fn parse_eval_jobs_line(line: &str) -> Option<(String, Evaluation)> {
+    let json: Value = serde_json::from_str(line).ok()?;
+    let attr = json.get("attr").and_then(Value::as_str)?;
+
+    let evaluation = match (
+        json.get("drvPath").and_then(Value::as_str),
+        json.get("error").and_then(Value::as_str),
+    ) {
+        (_, Some(error)) => Evaluation::Failed {
+            error: error.trim().to_string(),
+        },
+        (Some(drv_path), None) => Evaluation::Evaluated {
+            drv_path: drv_path.to_string(),
+        },
+        (None, None) => return None,
+    };
+
+    Some((attr.to_string(), evaluation))
+}
+
+fn cpu_count() -> usize {
+    std::thread::available_parallelism()
+        .map(|workers| workers.get())
+        .unwrap_or(1)
+}
+
+pub async fn nix_eval_jobs_available() -> bool {
+    Command::new("nix-eval-jobs")
+        .args(["--help"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .await
+        .map(|s| s.success())
+        .unwrap_or(false)
+}
+
+/// Spawning fails with `NotFound` when the binary isn't on PATH.
+fn spawn_error(bin: &str) -> impl FnOnce(std::io::Error) -> GarnixError + '_ {
+    move |e| {
+        if e.kind() == std::io::ErrorKind::NotFound {
+            GarnixError::NixNotInstalled(bin.to_string())
+        } else {
+            GarnixError::Io(e)
+        }
+    }
+}
+
+fn outcome_of(success: bool, interrupted: Option<BuildOutcome>) -> BuildOutcome {
+    match interrupted {
+        Some(outcome) => outcome,
+        None if success => BuildOutcome::Succeeded,
+        None => BuildOutcome::Failed,
+    }
+}
+
 pub async fn nom_available() -> bool {
     Command::new("nom")
         .args(["--version"])
@@ -211,4 +1293,210 @@ mod tests {
             "checks.x86_64-linux.test"
         );
     }
+
+    #[test]
+    fn test_extract_attributes_keeps_names_that_look_like_systems() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("flake.nix"), "{}").unwrap();
+        let flake = NixFlake::new(dir.path()).unwrap();
+        let show = serde_json::json!({
+            "packages": {
+                "x86_64-linux": {"hello": {"type": "derivation"}},
+                "aarch64-linux": {"hello": {"type": "derivation"}}
+            },
+            "nixosConfigurations": {
+                "x86_64-builder": {"type": "nixos-configuration"},
+                "web": {"type": "nixos-configuration"}
+            }
+        });
+
+        let mut attributes = Vec::new();
+        flake.extract_attributes(&show, Vec::new(), &mut attributes, "x86_64-linux");
+        assert_eq!(
+            attributes,
+            vec![
+                "nixosConfigurations.web",
+                "nixosConfigurations.x86_64-builder",
+                "packages.x86_64-linux.hello",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dry_run_plan_parse() {
+        let stderr = "\
+these 2 derivations will be built:
+  /nix/store/aaa-hello.drv
+  /nix/store/bbb-world.drv
+these 3 paths will be fetched (1.50 MiB download, 6.20 MiB unpacked):
+  /nix/store/ccc-glibc
+  /nix/store/ddd-bash
+  /nix/store/eee-coreutils
+";
+        let plan = DryRunPlan::parse(stderr);
+        assert_eq!(
+            plan.will_build,
+            vec!["/nix/store/aaa-hello.drv", "/nix/store/bbb-world.drv"]
+        );
+        assert_eq!(plan.will_fetch.len(), 3);
+        assert_eq!(plan.download_bytes, 1572864);
+        assert_eq!(plan.unpacked_bytes, 6501171);
+        assert_eq!(plan.cache_status(), CacheStatus::WillBuild);
+
+        let fetch_only = DryRunPlan::parse(
+            "this path will be fetched (0.01 MiB download, 0.05 MiB unpacked):\n  /nix/store/fff-hello\n",
+        );
+        assert_eq!(fetch_only.cache_status(), CacheStatus::WillSubstitute);
+
+        assert_eq!(DryRunPlan::parse("").cache_status(), CacheStatus::Cached);
+    }
+
+    #[test]
+    fn test_dry_run_plan_combine() {
+        let hello = DryRunPlan::parse(
+            "this derivation will be built:\n  /nix/store/aaa-hello.drv\nthese 2 paths will be fetched (1.00 MiB download, 2.00 MiB unpacked):\n  /nix/store/ccc-glibc\n  /nix/store/ddd-bash\n",
+        );
+        let world = DryRunPlan::parse(
+            "this path will be fetched (1.00 MiB download, 1.00 MiB unpacked):\n  /nix/store/ccc-glibc\n",
+        );
+
+        let separate = DryRunPlan::combine([&hello, &DryRunPlan::default()]);
+        assert_eq!(separate, hello);
+
+        let combined = DryRunPlan::combine([&hello, &world]);
+        assert_eq!(combined.will_build, vec!["/nix/store/aaa-hello.drv"]);
+        assert_eq!(
+            combined.will_fetch,
+            vec!["/nix/store/ccc-glibc", "/nix/store/ddd-bash"]
+        );
+        assert_eq!(combined.download_bytes, 2 * 1024 * 1024);
+        assert!(combined.shared_fetches);
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1572864), "1.50 MiB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.00 GiB");
+    }
+
+    #[test]
+    fn test_nix_version_parse() {
+        let nix = NixVersion::parse("nix (Nix) 2.18.1\n").unwrap();
+        assert_eq!(nix.implementation, NixImplementation::Nix);
+        assert_eq!((nix.major, nix.minor, nix.patch), (2, 18, 1));
+
+        let lix = NixVersion::parse("nix (Lix, like Nix) 2.91.1").unwrap();
+        assert_eq!(lix.implementation, NixImplementation::Lix);
+        assert_eq!(lix.to_string(), "lix 2.91.1");
+
+        let old = NixVersion::parse("nix (Nix) 2.3").unwrap();
+        assert!(!old.supports_internal_json_log());
+
+        assert!(NixVersion::parse("not nix").is_none());
+    }
+
+    #[test]
+    fn test_parse_nix_eval() {
+        assert_eq!(
+            parse_nix_eval(
+                true,
+                "/nix/store/abc-hello-1.0.drv\n",
+                "warning: Git tree '/src' is dirty\n"
+            ),
+            Evaluation::Evaluated {
+                drv_path: "/nix/store/abc-hello-1.0.drv".to_string()
+            }
+        );
+        assert_eq!(
+            parse_nix_eval(
+                false,
+                "",
+                "error:\n       … while evaluating the attribute 'drvPath'\n\n       error: attribute 'foo' missing\n"
+            ),
+            Evaluation::Failed {
+                error: "error:\n       … while evaluating the attribute 'drvPath'\n\n       error: attribute 'foo' missing".to_string()
+            }
+        );
+        assert!(matches!(
+            parse_nix_eval(true, "\n", ""),
+            Evaluation::Failed { .. }
+        ));
+    }
+
+    #[test]
+    fn test_parse_eval_jobs_line() {
+        assert_eq!(
+            parse_eval_jobs_line(
+                r#"{"attr":"a0","attrPath":["a0"],"drvPath":"/nix/store/abc-fmt.drv","name":"fmt","system":"x86_64-linux"}"#
+            ),
+            Some((
+                "a0".to_string(),
+                Evaluation::Evaluated {
+                    drv_path: "/nix/store/abc-fmt.drv".to_string()
+                }
+            ))
+        );
+
+        assert_eq!(
+            parse_eval_jobs_line(
+                r#"{"attr":"a1","attrPath":["a1"],"error":"error: attribute 'foo' missing\n"}"#
+            ),
+            Some((
+                "a1".to_string(),
+                Evaluation::Failed {
+                    error: "error: attribute 'foo' missing".to_string()
+                }
+            ))
+        );
+
+        assert_eq!(parse_eval_jobs_line("warning: foo"), None);
+    }
+
+    #[test]
+    fn test_eval_jobs_expr_selects_matched_attributes() {
+        let attributes = vec![
+            "checks.x86_64-linux.fmt".to_string(),
+            "nixosConfigurations.web".to_string(),
+        ];
+
+        assert_eq!(
+            eval_jobs_expr("/src/my \"flake\"", &attributes),
+            "let flake = builtins.getFlake \"/src/my \\\"flake\\\"\"; in {\n\
+             \x20 a0 = flake.\"checks\".\"x86_64-linux\".\"fmt\";\n\
+             \x20 a1 = flake.\"nixosConfigurations\".\"web\".\"config\".\"system\".\"build\".\"toplevel\";\n\
+             }"
+        );
+        assert_eq!(nix_string("${x}"), "\"\\${x}\"");
+
+        assert_eq!(eval_job_attribute(&attributes, "a1"), Some(&attributes[1]));
+        assert_eq!(eval_job_attribute(&attributes, "a2"), None);
+        assert_eq!(eval_job_attribute(&attributes, "fmt"), None);
+    }
+
+    #[test]
+    fn test_missing_experimental_features() {
+        let config: Value = serde_json::from_str(
+            r#"{"experimental-features":{"value":["nix-command","ca-derivations"]}}"#,
+        )
+        .unwrap();
+
+        let enabled = enabled_experimental_features(&config);
+        assert_eq!(enabled, vec!["nix-command", "ca-derivations"]);
+        assert_eq!(missing_features(&enabled), vec!["flakes"]);
+        assert!(missing_features(&["flakes".to_string(), "nix-command".to_string()]).is_empty());
+        assert!(experimental_features_help(&["flakes".to_string()]).contains("flakes"));
+    }
+
+    #[test]
+    fn test_disabled_experimental_features() {
+        let stderr = "error: experimental Nix feature 'flakes' is disabled; add '--extra-experimental-features flakes' to enable it\n\
+                      error: experimental Nix feature 'nix-command' is disabled\n\
+                      error: experimental Nix feature 'flakes' is disabled";
+        assert_eq!(
+            disabled_experimental_features(stderr),
+            vec!["flakes", "nix-command"]
+        );
+        assert!(disabled_experimental_features("error: flake.nix missing").is_empty());
+    }
 }
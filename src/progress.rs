@@ -0,0 +1,422 @@
+use colored::*;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+
+// Activity and result type ids from nix's `--log-format internal-json`.
+const ACT_FILE_TRANSFER: u64 = 101;
+const ACT_BUILD: u64 = 105;
+const ACT_SUBSTITUTE: u64 = 108;
+const RES_BUILD_LOG_LINE: u64 = 101;
+const RES_SET_PHASE: u64 = 104;
+const RES_POST_BUILD_LOG_LINE: u64 = 107;
+
+/// Messages above this level (warnings) are too chatty to show.
+const MAX_SHOWN_MESSAGE_LEVEL: u64 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LogEvent {
+    Start {
+        id: u64,
+        activity: Activity,
+    },
+    Stop {
+        id: u64,
+    },
+    BuildLogLine {
+        id: u64,
+        line: String,
+    },
+    SetPhase {
+        id: u64,
+        phase: String,
+    },
+    Message {
+        level: u64,
+        msg: String,
+    },
+    /// Anything else nix reports that we don't track.
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Activity {
+    Build { drv_path: String },
+    Substitute { store_path: String },
+    FileTransfer { url: String },
+    Other,
+}
+
+/// Parses one stderr line of `nix build --log-format internal-json`.
+/// Returns `None` for lines that aren't structured log messages.
+pub fn parse_line(line: &str) -> Option<LogEvent> {
+    let json: Value = serde_json::from_str(line.strip_prefix("@nix ")?).ok()?;
+    let id = json.get("id").and_then(Value::as_u64).unwrap_or(0);
+    let type_id = json.get("type").and_then(Value::as_u64).unwrap_or(0);
+    let first_field = || {
+        json.get("fields")
+            .and_then(|fields| fields.get(0))
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string()
+    };
+
+    let event = match json.get("action").and_then(Value::as_str)? {
+        "start" => LogEvent::Start {
+            id,
+            activity: match type_id {
+                ACT_BUILD => Activity::Build {
+                    drv_path: first_field(),
+                },
+                ACT_SUBSTITUTE => Activity::Substitute {
+                    store_path: first_field(),
+                },
+                ACT_FILE_TRANSFER => Activity::FileTransfer { url: first_field() },
+                _ => Activity::Other,
+            },
+        },
+        "stop" => LogEvent::Stop { id },
+        "result" => match type_id {
+            RES_BUILD_LOG_LINE | RES_POST_BUILD_LOG_LINE => LogEvent::BuildLogLine {
+                id,
+                line: first_field(),
+            },
+            RES_SET_PHASE => LogEvent::SetPhase {
+                id,
+                phase: first_field(),
+            },
+            _ => LogEvent::Other,
+        },
+        "msg" => LogEvent::Message {
+            level: json.get("level").and_then(Value::as_u64).unwrap_or(0),
+            msg: json
+                .get("msg")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string(),
+        },
+        _ => LogEvent::Other,
+    };
+
+    Some(event)
+}
+
+/// `/nix/store/<hash>-hello-1.0.drv` -> `hello-1.0`
+pub fn store_path_name(path: &str) -> &str {
+    let base = path.rsplit('/').next().unwrap_or(path);
+    let name = base.split_once('-').map_or(base, |(_, name)| name);
+    name.strip_suffix(".drv").unwrap_or(name)
+}
+
+/// The derivation named by nix's `builder for '<drv>' failed ...` error, with
+/// any terminal colours nix put around the path removed.
+fn failed_derivation(msg: &str) -> Option<String> {
+    let (_, rest) = msg.split_once("builder for '")?;
+    let (path, _) = rest.split_once('\'')?;
+    let mut plain = String::new();
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            chars.by_ref().find(|c| c.is_ascii_alphabetic());
+        } else {
+            plain.push(c);
+        }
+    }
+    Some(plain)
+}
+
+/// What happened while building one attribute.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BuildStats {
+    pub derivations_completed: Vec<String>,
+    pub paths_fetched: usize,
+}
+
+impl BuildStats {
+    /// Adds `other`'s counts to these, for a summary over several builds.
+    pub fn add(&mut self, other: &BuildStats) {
+        self.derivations_completed
+            .extend(other.derivations_completed.iter().cloned());
+        self.paths_fetched += other.paths_fetched;
+    }
+}
+
+/// Tracks nix activities for one attribute's build and renders progress lines.
+#[derive(Default)]
+pub struct ProgressTracker {
+    active: HashMap<u64, Activity>,
+    stats: BuildStats,
+    /// Derivations nix reported as failed. A build activity stops whether or
+    /// not its builder succeeded, so these are left out of the completed ones.
+    failed: HashSet<String>,
+}
+
+/// What the caller should do with an event besides tracking it.
+pub enum Output {
+    /// A build log line, already prefixed with the derivation name.
+    Log(String),
+    /// An error or warning from nix itself.
+    Message(String),
+    /// A status line for the terminal.
+    Status(String),
+    None,
+}
+
+impl ProgressTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn handle(&mut self, event: LogEvent) -> Output {
+        match event {
+            LogEvent::Start { id, activity } => {
+                let status = match &activity {
+                    Activity::Build { drv_path } => Output::Status(format!(
+                        "{} {}",
+                        "building".yellow(),
+                        store_path_name(drv_path)
+                    )),
+                    Activity::Substitute { store_path } => Output::Status(format!(
+                        "{} {}",
+                        "fetching".blue(),
+                        store_path_name(store_path)
+                    )),
+                    _ => Output::None,
+                };
+                self.active.insert(id, activity);
+                status
+            }
+            LogEvent::Stop { id } => match self.active.remove(&id) {
+                Some(Activity::Build { drv_path }) if self.failed.contains(&drv_path) => {
+                    Output::Status(format!(
+                        "{} {} ({})",
+                        "failed".red(),
+                        store_path_name(&drv_path),
+                        self.summary()
+                    ))
+                }
+                Some(Activity::Build { drv_path }) => {
+                    let name = store_path_name(&drv_path).to_string();
+                    self.stats.derivations_completed.push(drv_path);
+                    Output::Status(format!(
+                        "{} {} ({})",
+                        "finished".green(),
+                        name,
+                        self.summary()
+                    ))
+                }
+                Some(Activity::Substitute { .. }) => {
+                    self.stats.paths_fetched += 1;
+                    Output::None
+                }
+                _ => Output::None,
+            },
+            LogEvent::BuildLogLine { id, line } => Output::Log(match self.active.get(&id) {
+                Some(Activity::Build { drv_path }) => {
+                    format!("{}> {}", store_path_name(drv_path), line)
+                }
+                _ => line,
+            }),
+            LogEvent::SetPhase { id, phase } => match self.active.get(&id) {
+                Some(Activity::Build { drv_path }) => Output::Status(format!(
+                    "{} {}: {}",
+                    "building".yellow(),
+                    store_path_name(drv_path),
+                    phase
+                )),
+                _ => Output::None,
+            },
+            LogEvent::Message { level, msg } if level <= MAX_SHOWN_MESSAGE_LEVEL => {
+                if let Some(drv_path) = failed_derivation(&msg) {
+                    // nix may report the failure after the activity stopped.
+                    self.stats
+                        .derivations_completed
+                        .retain(|completed| *completed != drv_path);
+                    self.failed.insert(drv_path);
+                }
+                Output::Message(msg)
+            }
+            LogEvent::Message { .. } | LogEvent::Other => Output::None,
+        }
+    }
+
+    pub fn active_builds(&self) -> usize {
+        self.count_active(|activity| matches!(activity, Activity::Build { .. }))
+    }
+
+    pub fn active_downloads(&self) -> usize {
+        self.count_active(|activity| {
+            matches!(
+                activity,
+                Activity::Substitute { .. } | Activity::FileTransfer { .. }
+            )
+        })
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "{} building, {} downloading, {} completed, {} fetched",
+            self.active_builds(),
+            self.active_downloads(),
+            self.stats.derivations_completed.len(),
+            self.stats.paths_fetched
+        )
+    }
+
+    /// The stats for a build whose `nix build` exited with `success`. If it
+    /// failed without saying which derivation did, none of the builds that
+    /// stopped can be trusted to have finished.
+    pub fn into_stats(mut self, success: bool) -> BuildStats {
+        if !success && self.failed.is_empty() {
+            self.stats.derivations_completed.clear();
+        }
+        self.stats
+    }
+
+    fn count_active(&self, predicate: impl Fn(&Activity) -> bool) -> usize {
+        self.active
+            .values()
+            .filter(|activity| predicate(activity))
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(parse_line("plain stderr output"), None);
+
+        assert_eq!(
+            parse_line(
+                r#"@nix {"action":"start","id":7,"level":3,"type":105,"text":"building '/nix/store/abc-hello-1.0.drv'","fields":["/nix/store/abc-hello-1.0.drv","",1,1],"parent":0}"#
+            ),
+            Some(LogEvent::Start {
+                id: 7,
+                activity: Activity::Build {
+                    drv_path: "/nix/store/abc-hello-1.0.drv".to_string()
+                }
+            })
+        );
+
+        assert_eq!(
+            parse_line(r#"@nix {"action":"result","id":7,"type":101,"fields":["compiling"]}"#),
+            Some(LogEvent::BuildLogLine {
+                id: 7,
+                line: "compiling".to_string()
+            })
+        );
+
+        assert_eq!(
+            parse_line(r#"@nix {"action":"msg","level":0,"msg":"error: build failed"}"#),
+            Some(LogEvent::Message {
+                level: 0,
+                msg: "error: build failed".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_progress_tracker() {
+        let mut tracker = ProgressTracker::new();
+
+        tracker.handle(LogEvent::Start {
+            id: 1,
+            activity: Activity::Build {
+                drv_path: "/nix/store/abc-hello-1.0.drv".to_string(),
+            },
+        });
+        tracker.handle(LogEvent::Start {
+            id: 2,
+            activity: Activity::Substitute {
+                store_path: "/nix/store/def-glibc".to_string(),
+            },
+        });
+        assert_eq!(tracker.active_builds(), 1);
+        assert_eq!(tracker.active_downloads(), 1);
+
+        match tracker.handle(LogEvent::BuildLogLine {
+            id: 1,
+            line: "compiling".to_string(),
+        }) {
+            Output::Log(line) => assert_eq!(line, "hello-1.0> compiling"),
+            _ => panic!("expected a log line"),
+        }
+
+        tracker.handle(LogEvent::Stop { id: 2 });
+        tracker.handle(LogEvent::Stop { id: 1 });
+
+        assert_eq!(tracker.active_builds(), 0);
+        assert_eq!(
+            tracker.into_stats(true),
+            BuildStats {
+                derivations_completed: vec!["/nix/store/abc-hello-1.0.drv".to_string()],
+                paths_fetched: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_progress_tracker_failed_build() {
+        let start = |id, drv_path: &str| LogEvent::Start {
+            id,
+            activity: Activity::Build {
+                drv_path: drv_path.to_string(),
+            },
+        };
+        let failure = |drv_path: &str| LogEvent::Message {
+            level: 0,
+            msg: format!(
+                "error: builder for '\x1b[35;1m{}\x1b[0m' failed with exit code 1",
+                drv_path
+            ),
+        };
+
+        let mut tracker = ProgressTracker::new();
+        tracker.handle(start(1, "/nix/store/abc-dep.drv"));
+        tracker.handle(start(2, "/nix/store/def-hello.drv"));
+        tracker.handle(start(3, "/nix/store/ghi-other.drv"));
+        tracker.handle(LogEvent::Stop { id: 1 });
+        // Reported after its activity stopped.
+        tracker.handle(LogEvent::Stop { id: 2 });
+        tracker.handle(failure("/nix/store/def-hello.drv"));
+        // Reported before its activity stopped.
+        tracker.handle(failure("/nix/store/ghi-other.drv"));
+        match tracker.handle(LogEvent::Stop { id: 3 }) {
+            Output::Status(status) => assert!(status.contains("failed")),
+            _ => panic!("expected a status line"),
+        }
+
+        assert_eq!(
+            tracker.into_stats(false).derivations_completed,
+            vec!["/nix/store/abc-dep.drv"]
+        );
+
+        let mut tracker = ProgressTracker::new();
+        tracker.handle(start(1, "/nix/store/abc-dep.drv"));
+        tracker.handle(LogEvent::Stop { id: 1 });
+        assert!(tracker.into_stats(false).derivations_completed.is_empty());
+    }
+
+    #[test]
+    fn test_store_path_name() {
+        assert_eq!(store_path_name("/nix/store/abc-hello-1.0.drv"), "hello-1.0");
+        assert_eq!(store_path_name("/nix/store/abc-glibc-2.39"), "glibc-2.39");
+    }
+
+    #[test]
+    fn test_build_stats_add() {
+        let mut total = BuildStats::default();
+        total.add(&BuildStats {
+            derivations_completed: vec!["hello-1.0".to_string()],
+            paths_fetched: 2,
+        });
+        total.add(&BuildStats {
+            derivations_completed: vec!["fmt".to_string()],
+            paths_fetched: 3,
+        });
+        assert_eq!(total.derivations_completed, vec!["hello-1.0", "fmt"]);
+        assert_eq!(total.paths_fetched, 5);
+    }
+}
@@ -0,0 +1,214 @@
+use crate::cli;
+use crate::error::Result;
+use std::process::ExitStatus;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::process::Child;
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+/// How far the user has asked garnix to stop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Stop {
+    Running,
+    /// Wind down: interrupt children and wait for them.
+    Cancelled,
+    /// Kill children that are still winding down.
+    Forced,
+}
+
+/// Shared flag flipped when the user asks garnix to stop.
+#[derive(Clone)]
+pub struct CancelToken {
+    sender: Arc<watch::Sender<Stop>>,
+}
+
+impl Default for CancelToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self {
+            sender: Arc::new(watch::channel(Stop::Running).0),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.sender.send_if_modified(|stop| {
+            let running = *stop == Stop::Running;
+            if running {
+                *stop = Stop::Cancelled;
+            }
+            running
+        });
+    }
+
+    /// Cancels, and also stops waiting for interrupted children to exit.
+    pub fn force(&self) {
+        self.sender.send_replace(Stop::Forced);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.sender.borrow() >= Stop::Cancelled
+    }
+
+    /// Resolves once the token has been cancelled.
+    pub async fn cancelled(&self) {
+        self.wait_for(Stop::Cancelled).await;
+    }
+
+    /// Resolves once the stop has been forced.
+    pub async fn forced(&self) {
+        self.wait_for(Stop::Forced).await;
+    }
+
+    async fn wait_for(&self, level: Stop) {
+        let mut receiver = self.sender.subscribe();
+        let _ = receiver.wait_for(|stop| *stop >= level).await;
+    }
+}
+
+/// Cancels the token on SIGINT or SIGTERM and forces the stop on a second
+/// one; a third exits right away. This replaces the default "exit
+/// immediately" behaviour for the rest of the process, so it should only be
+/// installed once there are child processes to wind down.
+pub fn cancel_on_signal(token: CancelToken) -> Result<()> {
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+
+    tokio::spawn(async move {
+        for received in 1.. {
+            tokio::select! {
+                _ = interrupt.recv() => {}
+                _ = terminate.recv() => {}
+            }
+            match received {
+                1 => {
+                    cli::print_warning("stopping builds, interrupt again to kill them");
+                    token.cancel();
+                }
+                2 => {
+                    cli::print_warning("killing builds, interrupt again to exit immediately");
+                    token.force();
+                }
+                _ => std::process::exit(130),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// How long an interrupted child gets to exit before it's killed.
+pub const KILL_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// Resolves when an interrupted child should be killed: at `deadline`, or as
+/// soon as the stop is forced.
+pub async fn kill_due(deadline: Option<Instant>, cancel: &CancelToken) {
+    let deadline = async {
+        match deadline {
+            Some(deadline) => tokio::time::sleep_until(deadline).await,
+            None => std::future::pending().await,
+        }
+    };
+
+    tokio::select! {
+        _ = deadline => {}
+        _ = cancel.forced() => {}
+    }
+}
+
+/// Interrupts `child` and waits for it to exit, killing it if it's still
+/// running after `grace` or once the stop is forced.
+pub async fn stop_child(
+    child: &mut Child,
+    grace: Duration,
+    cancel: &CancelToken,
+) -> std::io::Result<ExitStatus> {
+    interrupt_child(child.id());
+    tokio::select! {
+        status = child.wait() => return status,
+        _ = kill_due(Some(Instant::now() + grace), cancel) => {}
+    }
+    child.kill().await?;
+    child.wait().await
+}
+
+/// Asks a child process to stop the way a terminal Ctrl-C would.
+pub fn interrupt_child(pid: Option<u32>) {
+    if let Some(pid) = pid {
+        // SAFETY: kill(2) has no memory-safety preconditions; a stale pid only yields ESRCH.
+        unsafe {
+            libc::kill(pid as libc::pid_t, libc::SIGINT);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cancel_token() {
+        let token = CancelToken::new();
+        assert!(!token.is_cancelled());
+
+        let waiter = tokio::spawn({
+            let token = token.clone();
+            async move { token.cancelled().await }
+        });
+
+        token.cancel();
+        waiter.await.unwrap();
+        assert!(token.is_cancelled());
+
+        // Already cancelled tokens resolve immediately.
+        token.cancelled().await;
+
+        // Cancelling again doesn't undo forcing.
+        token.force();
+        token.cancel();
+        token.forced().await;
+    }
+
+    #[tokio::test]
+    async fn test_stop_child_kills_after_grace_period() {
+        let mut child = tokio::process::Command::new("sh")
+            .args(["-c", "trap '' INT; sleep 30"])
+            .spawn()
+            .unwrap();
+        // Give sh a moment to install the trap.
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let started = std::time::Instant::now();
+        let status = stop_child(&mut child, Duration::from_millis(200), &CancelToken::new())
+            .await
+            .unwrap();
+        assert!(!status.success());
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[tokio::test]
+    async fn test_forcing_skips_grace_period() {
+        let mut child = tokio::process::Command::new("sh")
+            .args(["-c", "trap '' INT; sleep 30"])
+            .spawn()
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let token = CancelToken::new();
+        token.force();
+        assert!(token.is_cancelled());
+
+        let started = std::time::Instant::now();
+        let status = stop_child(&mut child, Duration::from_secs(60), &token)
+            .await
+            .unwrap();
+        assert!(!status.success());
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+}